@@ -0,0 +1,700 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+use hyper::{body::Body, Uri};
+use hyper_util::{
+    client::legacy::{
+        connect::{Connected, Connection},
+        Builder, Client,
+    },
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+};
+use rustls::{ClientConfig, ServerName};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+use crate::{
+    error::NetworkError,
+    tls::trust::TrustStore,
+    utils::{get_random_int, random_seed, with_seed, BrowserPreset, TlsProfile},
+};
+
+/// how to open a TLS over TCP connection with a randomized fingerprint
+///
+/// a fresh random `TlsProfile` is drawn per connector unless one or a seed is given,
+/// servers are verified against the webpki roots by default.
+#[derive(Debug, Clone)]
+pub struct TlsStreamOptions {
+    profile: Option<TlsProfile>,
+    seed: Option<u64>,
+    trust: TrustStore,
+    server_name: Option<String>,
+    alpn_protocols: Option<Vec<Vec<u8>>>,
+    connect_timeout: Duration,
+    handshake_timeout: Duration,
+}
+
+impl Default for TlsStreamOptions {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            seed: None,
+            trust: TrustStore::new().with_webpki_roots(),
+            server_name: None,
+            alpn_protocols: None,
+            connect_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl TlsStreamOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// use `profile` instead of a random one
+    pub fn with_profile(mut self, profile: TlsProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// draw the random profile from `seed`, so every connector gets the same one
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_trust(mut self, trust: TrustStore) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    /// name sent in SNI and verified against the certificate, the host by default
+    pub fn with_server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.to_string());
+        self
+    }
+
+    /// protocols offered in ALPN, overriding the profile's
+    pub fn with_alpn_protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.alpn_protocols = Some(protocols.into_iter().map(|p| p.as_ref().to_vec()).collect());
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn connector(&self) -> Result<TlsConnector> {
+        Ok(TlsConnector::from(self.client_config()?))
+    }
+
+    fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        let profile = self.profile.clone().unwrap_or_else(|| {
+            with_seed(self.seed.unwrap_or_else(random_seed), TlsProfile::random)
+        });
+        let mut config = profile.client_config(&self.trust)?;
+        if let Some(protocols) = self.alpn_protocols.as_ref() {
+            config.alpn_protocols = protocols.clone();
+        }
+        Ok(Arc::new(config))
+    }
+
+    /// connect to `host:port` and complete the handshake
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TlsStream<TcpStream>> {
+        let connector = self.connector()?;
+        let tcp = self.connect_tcp(host, port).await?;
+        self.handshake(&connector, host, tcp).await
+    }
+
+    async fn connect_tcp(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let tcp = timeout(
+            "connect",
+            self.connect_timeout,
+            TcpStream::connect((host, port)),
+        )
+        .await?;
+        tcp.set_nodelay(true)?;
+        Ok(tcp)
+    }
+
+    async fn handshake(
+        &self,
+        connector: &TlsConnector,
+        host: &str,
+        tcp: TcpStream,
+    ) -> Result<TlsStream<TcpStream>> {
+        let server_name = ServerName::try_from(self.server_name.as_deref().unwrap_or(host))?;
+        let stream = timeout(
+            "tls handshake",
+            self.handshake_timeout,
+            connector.connect(server_name, tcp),
+        )
+        .await?;
+        Ok(stream)
+    }
+}
+
+async fn timeout<T>(
+    what: &str,
+    duration: Duration,
+    future: impl Future<Output = std::io::Result<T>>,
+) -> Result<T, NetworkError> {
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(NetworkError::Timeout(format!(
+            "{} after {:?}",
+            what, duration
+        ))),
+    }
+}
+
+/// connector with random cipher suites, key exchange group and protocol versions
+pub fn get_random_tls_connector() -> Result<TlsConnector> {
+    TlsStreamOptions::new().connector()
+}
+
+/// the connector `get_random_tls_connector` built when it logged `seed`
+pub fn get_seeded_tls_connector(seed: u64) -> Result<TlsConnector> {
+    TlsStreamOptions::new().with_seed(seed).connector()
+}
+
+/// open a TLS stream to `addr:port` using a randomized connector
+///
+/// the profile is drawn when this is called rather than when the future is polled, so
+/// the call can be wrapped in `with_seed`.
+pub fn get_random_tls_stream(
+    addr: &str,
+    port: u16,
+) -> impl Future<Output = Result<TlsStream<TcpStream>>> + '_ {
+    let options = TlsStreamOptions::new().with_seed(random_seed());
+    async move { options.connect(addr, port).await }
+}
+
+/// a plain or TLS connection handed to hyper by `HttpsConnector`
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.connected(),
+            MaybeTlsStream::Tls(stream) => {
+                let (tcp, session) = stream.get_ref();
+                match session.alpn_protocol() {
+                    Some(b"h2") => tcp.connected().negotiated_h2(),
+                    _ => tcp.connected(),
+                }
+            }
+        }
+    }
+}
+
+/// hyper connector for `http` and `https` uris using `TlsStreamOptions`
+///
+/// the TLS config is built on the first `https` connection and shared by every later one,
+/// including those of clones.
+#[derive(Debug, Clone)]
+pub struct HttpsConnector {
+    options: Arc<TlsStreamOptions>,
+    config: Arc<OnceLock<Arc<ClientConfig>>>,
+}
+
+impl HttpsConnector {
+    /// a random profile is drawn from a seed taken here, so `with_seed` around this call
+    /// covers the connections made later
+    pub fn new(mut options: TlsStreamOptions) -> Self {
+        if options.profile.is_none() && options.seed.is_none() {
+            options.seed = Some(random_seed());
+        }
+        Self {
+            options: Arc::new(options),
+            config: Arc::new(OnceLock::new()),
+        }
+    }
+
+    fn tls_connector(&self) -> Result<TlsConnector> {
+        if let Some(config) = self.config.get() {
+            return Ok(TlsConnector::from(config.clone()));
+        }
+        let config = self.options.client_config()?;
+        Ok(TlsConnector::from(
+            self.config.get_or_init(|| config).clone(),
+        ))
+    }
+}
+
+impl tower_service::Service<Uri> for HttpsConnector {
+    type Response = TokioIo<MaybeTlsStream>;
+    type Error = anyhow::Error;
+    type Future = BoxFuture<'static, Result<Self::Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| anyhow::anyhow!("uri has no host: {}", uri))?
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            let stream = match uri.scheme_str() {
+                Some("https") => {
+                    let tls = connector.tls_connector()?;
+                    let tcp = connector
+                        .options
+                        .connect_tcp(host, uri.port_u16().unwrap_or(443))
+                        .await?;
+                    MaybeTlsStream::Tls(Box::new(
+                        connector.options.handshake(&tls, host, tcp).await?,
+                    ))
+                }
+                Some("http") => MaybeTlsStream::Plain(
+                    connector
+                        .options
+                        .connect_tcp(host, uri.port_u16().unwrap_or(80))
+                        .await?,
+                ),
+                _ => return Err(anyhow::anyhow!("unsupported scheme in {}", uri)),
+            };
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+/// the HTTP/2 SETTINGS and connection window a client announces
+///
+/// together with the TLS profile these make up the connection's fingerprint. the order
+/// of the settings, the pseudo-header order and the frames sent are fixed by `h2`, so
+/// only the values can be matched to a browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Http2Settings {
+    /// `None` keeps the 4096 byte protocol default and leaves it out of SETTINGS
+    pub header_table_size: Option<u32>,
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_stream_window_size: u32,
+    pub initial_connection_window_size: u32,
+    pub max_frame_size: u32,
+    /// always sent, `h2` cannot leave it out. Firefox and Safari do not send it, their
+    /// presets use 64 KiB which is what they accept in practice
+    pub max_header_list_size: u32,
+    /// resize windows from measured bandwidth, no browser does this
+    pub adaptive_window: bool,
+}
+
+impl Http2Settings {
+    pub fn chrome() -> Self {
+        Self {
+            header_table_size: Some(65536),
+            max_concurrent_streams: None,
+            initial_stream_window_size: 6291456,
+            initial_connection_window_size: 15728640,
+            max_frame_size: 16384,
+            max_header_list_size: 262144,
+            adaptive_window: false,
+        }
+    }
+
+    pub fn firefox() -> Self {
+        Self {
+            header_table_size: Some(65536),
+            max_concurrent_streams: None,
+            initial_stream_window_size: 131072,
+            initial_connection_window_size: 12582912,
+            max_frame_size: 16384,
+            max_header_list_size: 65536,
+            adaptive_window: false,
+        }
+    }
+
+    pub fn safari() -> Self {
+        Self {
+            header_table_size: None,
+            max_concurrent_streams: Some(100),
+            initial_stream_window_size: 2097152,
+            initial_connection_window_size: 10551295,
+            max_frame_size: 16384,
+            max_header_list_size: 65536,
+            adaptive_window: false,
+        }
+    }
+
+    pub fn for_preset(preset: BrowserPreset) -> Self {
+        match preset {
+            BrowserPreset::Chrome80 | BrowserPreset::Chrome100 => Self::chrome(),
+            BrowserPreset::FirefoxEsr | BrowserPreset::Firefox100 => Self::firefox(),
+            BrowserPreset::Safari15 => Self::safari(),
+        }
+    }
+
+    /// random values within the ranges browsers and servers accept
+    pub fn random() -> Self {
+        let pick = |values: &[u32]| values[get_random_int(0, values.len())];
+        Self {
+            header_table_size: [None, Some(4096), Some(65536)][get_random_int(0, 3)],
+            max_concurrent_streams: [None, Some(100), Some(1000)][get_random_int(0, 3)],
+            initial_stream_window_size: get_random_int(64 * 1024, 6 * 1024 * 1024 + 1) as u32,
+            initial_connection_window_size: get_random_int(1024 * 1024, 15 * 1024 * 1024 + 1)
+                as u32,
+            max_frame_size: pick(&[16384, 16384, 32768, 65536]),
+            max_header_list_size: pick(&[16384, 65536, 262144]),
+            adaptive_window: get_random_int(0, 4) == 0,
+        }
+    }
+
+    fn apply(&self, builder: &mut Builder) {
+        builder
+            .http2_header_table_size(self.header_table_size)
+            .http2_max_concurrent_streams(self.max_concurrent_streams)
+            .http2_initial_stream_window_size(self.initial_stream_window_size)
+            .http2_initial_connection_window_size(self.initial_connection_window_size)
+            .http2_max_frame_size(self.max_frame_size)
+            .http2_max_header_list_size(self.max_header_list_size)
+            .http2_adaptive_window(self.adaptive_window);
+    }
+}
+
+/// pool and protocol settings of a randomized client, kept so they can be logged
+#[derive(Debug, Clone)]
+pub struct ClientParams {
+    /// replays the random choices with `ClientParams::from_seed`, none for preset clients
+    /// which are not replayable
+    pub seed: Option<u64>,
+    pub tls_profile: TlsProfile,
+    pub pool_idle_timeout: Duration,
+    pub pool_max_idle_per_host: usize,
+    pub http1_max_buf_size: usize,
+    pub http2: Http2Settings,
+}
+
+impl ClientParams {
+    /// random values within the ranges browsers and servers accept
+    pub fn random() -> Self {
+        Self::from_seed(random_seed())
+    }
+
+    /// the same values `random` picked when it reported `seed`
+    pub fn from_seed(seed: u64) -> Self {
+        with_seed(seed, || Self {
+            seed: Some(seed),
+            tls_profile: TlsProfile::random(),
+            pool_idle_timeout: Duration::from_secs(get_random_int(30, 121) as u64),
+            pool_max_idle_per_host: get_random_int(1, 33),
+            // hyper rejects buffers under 8 KiB
+            http1_max_buf_size: get_random_int(8 * 1024, 400 * 1024 + 1),
+            http2: Http2Settings::random(),
+        })
+    }
+
+    /// TLS and HTTP/2 settings of `preset`, with random pool settings
+    ///
+    /// draws nothing but the three pool values from the current random source.
+    pub fn for_preset(preset: BrowserPreset) -> Self {
+        Self {
+            seed: None,
+            tls_profile: preset.tls_profile(),
+            pool_idle_timeout: Duration::from_secs(get_random_int(30, 121) as u64),
+            pool_max_idle_per_host: get_random_int(1, 33),
+            http1_max_buf_size: get_random_int(8 * 1024, 400 * 1024 + 1),
+            http2: Http2Settings::for_preset(preset),
+        }
+    }
+
+    /// client with these settings, connecting with `options` and this TLS profile
+    ///
+    /// `h2` and `http/1.1` are offered in ALPN when the profile has no protocols.
+    pub fn client<B>(&self, options: TlsStreamOptions) -> Client<HttpsConnector, B>
+    where
+        B: Body + Send,
+        B::Data: Send,
+    {
+        let mut profile = self.tls_profile.clone();
+        if profile.alpn_protocols.is_empty() {
+            profile = profile.with_alpn_protocols(["h2", "http/1.1"]);
+        }
+        let mut builder = Client::builder(TokioExecutor::new());
+        builder
+            .pool_timer(TokioTimer::new())
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .http1_max_buf_size(self.http1_max_buf_size);
+        self.http2.apply(&mut builder);
+        builder.build(HttpsConnector::new(options.with_profile(profile)))
+    }
+}
+
+/// hyper client with a random TLS profile, pool timeout, buffer sizes and HTTP/2 windows
+pub fn get_random_client<B>() -> (Client<HttpsConnector, B>, ClientParams)
+where
+    B: Body + Send,
+    B::Data: Send,
+{
+    get_seeded_client(random_seed())
+}
+
+/// the client `get_random_client` built when it logged `seed`
+pub fn get_seeded_client<B>(seed: u64) -> (Client<HttpsConnector, B>, ClientParams)
+where
+    B: Body + Send,
+    B::Data: Send,
+{
+    let params = ClientParams::from_seed(seed);
+    tracing::debug!("random client params {:?}", params);
+    (params.client(TlsStreamOptions::new()), params)
+}
+
+/// hyper client imitating `preset` in both its TLS and HTTP/2 settings
+pub fn get_browser_client<B>(preset: BrowserPreset) -> (Client<HttpsConnector, B>, ClientParams)
+where
+    B: Body + Send,
+    B::Data: Send,
+{
+    let params = ClientParams::for_preset(preset);
+    tracing::debug!("{:?} client params {:?}", preset, params);
+    (params.client(TlsStreamOptions::new()), params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quic::common;
+    use rustls::{Certificate, PrivateKey, ServerConfig};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_tls_stream_negotiates_alpn() -> anyhow::Result<()> {
+        let cert = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .build()?;
+        let der = Certificate(cert.serialize_der()?);
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![der.clone()],
+                PrivateKey(cert.serialize_private_key_der()),
+            )?;
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await?;
+            let _stream = acceptor.accept(tcp).await?;
+            anyhow::Ok(())
+        });
+
+        let options = TlsStreamOptions::new()
+            .with_trust(TrustStore::new().with_certificate(der))
+            .with_server_name("localhost")
+            .with_alpn_protocols(["h2", "http/1.1"]);
+        let stream = options.connect("127.0.0.1", port).await?;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        // a listener that never answers the handshake
+        let silent = TcpListener::bind("127.0.0.1:0").await?;
+        let port = silent.local_addr()?.port();
+        let result = options
+            .with_handshake_timeout(Duration::from_millis(100))
+            .connect("127.0.0.1", port)
+            .await;
+        assert!(matches!(
+            result.unwrap_err().downcast::<NetworkError>()?,
+            NetworkError::Timeout(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_https_connector_reuses_config() -> anyhow::Result<()> {
+        let connector = HttpsConnector::new(TlsStreamOptions::new());
+        connector.tls_connector()?;
+        let first = connector.config.get().cloned().expect("built on first use");
+        connector.clone().tls_connector()?;
+        assert!(Arc::ptr_eq(&first, connector.config.get().unwrap()));
+
+        // the seed is taken when the connector is built, inside `with_seed`
+        let seeded = with_seed(11, || HttpsConnector::new(TlsStreamOptions::new()));
+        let replayed = with_seed(11, || HttpsConnector::new(TlsStreamOptions::new()));
+        assert_eq!(seeded.options.seed, replayed.options.seed);
+        assert!(seeded.options.seed.is_some());
+        assert_eq!(
+            ClientParams::for_preset(BrowserPreset::Chrome100).seed,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_random_client_requests() -> anyhow::Result<()> {
+        use http_body_util::{BodyExt, Empty, Full};
+        use hyper::{body::Bytes, service::service_fn, Request, Response};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await?;
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(
+                    TokioIo::new(tcp),
+                    service_fn(|_req| async {
+                        Ok::<_, std::convert::Infallible>(Response::new(Full::new(Bytes::from(
+                            "hello",
+                        ))))
+                    }),
+                )
+                .await?;
+            anyhow::Ok(())
+        });
+
+        let (client, params) = get_random_client::<Empty<Bytes>>();
+        assert!(params.http1_max_buf_size >= 8 * 1024);
+        let response = client
+            .request(Request::get(format!("http://{}/", addr)).body(Empty::new())?)
+            .await?;
+        let body = response.into_body().collect().await?.to_bytes();
+        assert_eq!(body, Bytes::from("hello"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_browser_client_sends_http2_settings() -> anyhow::Result<()> {
+        use http_body_util::Empty;
+        use hyper::{body::Bytes, Request};
+        use tokio::io::AsyncReadExt;
+
+        let cert = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .build()?;
+        let der = Certificate(cert.serialize_der()?);
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![der.clone()],
+                PrivateKey(cert.serialize_private_key_der()),
+            )?;
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        // read the preface and the SETTINGS and WINDOW_UPDATE frames that follow it
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await?;
+            let mut stream = acceptor.accept(tcp).await?;
+            let mut preface = [0u8; 24];
+            stream.read_exact(&mut preface).await?;
+            assert_eq!(&preface, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+            let mut frames = Vec::new();
+            for _ in 0..2 {
+                let mut header = [0u8; 9];
+                stream.read_exact(&mut header).await?;
+                let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+                let mut payload = vec![0u8; len];
+                stream.read_exact(&mut payload).await?;
+                frames.push((header[3], payload));
+            }
+            anyhow::Ok(frames)
+        });
+
+        let params = ClientParams::for_preset(BrowserPreset::Chrome100);
+        let client = params.client::<Empty<Bytes>>(
+            TlsStreamOptions::new()
+                .with_trust(TrustStore::new().with_certificate(der))
+                .with_server_name("localhost"),
+        );
+        let request = Request::get(format!("https://localhost:{}/", port)).body(Empty::new())?;
+        tokio::spawn(async move { client.request(request).await });
+
+        let frames = server.await??;
+        let (kind, payload) = &frames[0];
+        assert_eq!(*kind, 0x4, "SETTINGS comes first");
+        let settings: std::collections::HashMap<u16, u32> = payload
+            .chunks_exact(6)
+            .map(|s| {
+                (
+                    u16::from_be_bytes([s[0], s[1]]),
+                    u32::from_be_bytes([s[2], s[3], s[4], s[5]]),
+                )
+            })
+            .collect();
+        let http2 = Http2Settings::chrome();
+        assert_eq!(settings.get(&0x1), http2.header_table_size.as_ref());
+        assert_eq!(settings.get(&0x3), http2.max_concurrent_streams.as_ref());
+        assert_eq!(settings[&0x4], http2.initial_stream_window_size);
+        assert_eq!(settings[&0x5], http2.max_frame_size);
+        assert_eq!(settings[&0x6], http2.max_header_list_size);
+
+        let (kind, payload) = &frames[1];
+        assert_eq!(*kind, 0x8, "WINDOW_UPDATE for the connection follows");
+        let increment = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        assert_eq!(increment, http2.initial_connection_window_size - 65535);
+        Ok(())
+    }
+}
@@ -18,18 +18,46 @@ pub enum NetworkError {
     InternalError(String),
     #[error("unknownerror {0}")]
     Unknown(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
     #[error("Hyper Error")]
     HyperError(#[from] hyper::Error),
+    #[error("{0} parse error: {1}")]
+    FormatError(FileFormat, String),
+    #[error("certificate for {host} changed, expected {expected} but got {presented}")]
+    HostCertificateChanged {
+        host: String,
+        expected: String,
+        presented: String,
+    },
+}
+
+/// encoding detected when reading key and certificate files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Pem,
+    Der,
+    Pkcs12,
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileFormat::Pem => write!(f, "PEM"),
+            FileFormat::Der => write!(f, "DER"),
+            FileFormat::Pkcs12 => write!(f, "PKCS#12"),
+        }
+    }
 }
 
 impl From<Box<dyn std::error::Error>> for NetworkError {
     fn from(value: Box<dyn std::error::Error>) -> Self {
-        return Self::InternalError(value.to_string());
+        Self::InternalError(value.to_string())
     }
 }
 
 impl From<anyhow::Error> for NetworkError {
     fn from(value: anyhow::Error) -> Self {
-        return Self::InternalError(value.to_string());
+        Self::InternalError(value.to_string())
     }
 }
@@ -0,0 +1,476 @@
+use anyhow::Result;
+use http::{header, HeaderMap, HeaderName, HeaderValue, Request};
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    user_agent::{product_version, Browser, DeviceClass, Os, UserAgentInfo},
+    utils::{get_random_usr_agent, SharedRng},
+};
+
+/// a random user agent as a header value
+pub fn get_random_user_agent_headval() -> HeaderValue {
+    HeaderValue::from_str(&get_random_usr_agent()).expect("user agents are valid header values")
+}
+
+/// layer setting the User-Agent of every request, replacing any already present
+///
+/// `random` picks a new agent per request, `sticky` picks one when the layer is built
+/// and keeps it for every service it wraps, so a session looks like a single browser.
+#[derive(Debug, Clone)]
+pub struct UserAgentLayer {
+    agent: Option<HeaderValue>,
+    rng: Option<SharedRng>,
+}
+
+impl UserAgentLayer {
+    pub fn random() -> Self {
+        Self {
+            agent: None,
+            rng: None,
+        }
+    }
+
+    /// like `random` with every agent drawn from `seed`, shared by all services the layer
+    /// wraps, so the same requests in the same order get the same agents
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            agent: None,
+            rng: Some(SharedRng::from_seed(seed)),
+        }
+    }
+
+    pub fn sticky() -> Self {
+        Self::fixed(get_random_user_agent_headval())
+    }
+
+    pub fn fixed(agent: HeaderValue) -> Self {
+        Self {
+            agent: Some(agent),
+            rng: None,
+        }
+    }
+
+    /// the agent sent by a sticky or fixed layer
+    pub fn agent(&self) -> Option<&HeaderValue> {
+        self.agent.as_ref()
+    }
+}
+
+impl<S> Layer<S> for UserAgentLayer {
+    type Service = UserAgent<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        UserAgent {
+            inner,
+            agent: self.agent.clone(),
+            rng: self.rng.clone(),
+        }
+    }
+}
+
+/// service built by `UserAgentLayer`
+#[derive(Debug, Clone)]
+pub struct UserAgent<S> {
+    inner: S,
+    agent: Option<HeaderValue>,
+    rng: Option<SharedRng>,
+}
+
+impl<S, B> Service<Request<B>> for UserAgent<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let agent = match (&self.agent, &self.rng) {
+            (Some(agent), _) => agent.clone(),
+            (None, Some(rng)) => rng.scope(get_random_user_agent_headval),
+            (None, None) => get_random_user_agent_headval(),
+        };
+        req.headers_mut().insert(header::USER_AGENT, agent);
+        self.inner.call(req)
+    }
+}
+
+const CHROME_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+const FIREFOX_ACCEPT: &str =
+    "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+const SAFARI_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+/// the headers a browser sends when navigating to a page, in the browser's order
+///
+/// everything is derived from the user agent, so Accept, Accept-Encoding, client hints
+/// and fetch metadata agree with the browser, version and platform it claims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrowserHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl BrowserHeaders {
+    pub fn for_user_agent(user_agent: &str) -> Result<Self> {
+        let info = UserAgentInfo::parse(user_agent);
+        let chromium = product_version(user_agent, "Chrome/");
+        let mut headers: Vec<(HeaderName, String)> = Vec::new();
+        let mut add = |name: &'static str, value: &str| {
+            headers.push((HeaderName::from_static(name), value.to_string()));
+        };
+        let fetch_metadata = [
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-user", "?1"),
+            ("sec-fetch-dest", "document"),
+        ];
+
+        match (info.browser, chromium) {
+            (Browser::Chrome | Browser::Edge | Browser::Opera, Some(chromium)) => {
+                // client hints shipped in Chromium 89
+                if chromium >= 89 {
+                    let brand = match info.browser {
+                        Browser::Edge => format!("\"Microsoft Edge\";v=\"{}\"", info.version),
+                        Browser::Opera => format!("\"Opera\";v=\"{}\"", info.version),
+                        _ => format!("\"Google Chrome\";v=\"{}\"", chromium),
+                    };
+                    add(
+                        "sec-ch-ua",
+                        &format!(
+                            "\"Chromium\";v=\"{}\", {}, \"Not.A/Brand\";v=\"99\"",
+                            chromium, brand
+                        ),
+                    );
+                    let mobile = if info.device == DeviceClass::Mobile {
+                        "?1"
+                    } else {
+                        "?0"
+                    };
+                    add("sec-ch-ua-mobile", mobile);
+                    add(
+                        "sec-ch-ua-platform",
+                        &format!("\"{}\"", platform_hint(info.os)),
+                    );
+                }
+                add("upgrade-insecure-requests", "1");
+                add("user-agent", user_agent);
+                add("accept", CHROME_ACCEPT);
+                for (name, value) in fetch_metadata {
+                    add(name, value);
+                }
+                let encoding = if chromium >= 123 {
+                    "gzip, deflate, br, zstd"
+                } else {
+                    "gzip, deflate, br"
+                };
+                add("accept-encoding", encoding);
+                add("accept-language", "en-US,en;q=0.9");
+            }
+            (Browser::Firefox, _) => {
+                add("user-agent", user_agent);
+                add("accept", FIREFOX_ACCEPT);
+                add("accept-language", "en-US,en;q=0.5");
+                let encoding = if info.version >= 126 {
+                    "gzip, deflate, br, zstd"
+                } else {
+                    "gzip, deflate, br"
+                };
+                add("accept-encoding", encoding);
+                add("upgrade-insecure-requests", "1");
+                // fetch metadata shipped in Firefox 90
+                if info.version >= 90 {
+                    for (name, value) in [
+                        fetch_metadata[3],
+                        fetch_metadata[1],
+                        fetch_metadata[0],
+                        fetch_metadata[2],
+                    ] {
+                        add(name, value);
+                    }
+                }
+            }
+            (Browser::Safari, _) if info.version >= 17 => {
+                add("accept", SAFARI_ACCEPT);
+                add("sec-fetch-site", "none");
+                add("accept-language", "en-US,en;q=0.9");
+                add("sec-fetch-mode", "navigate");
+                add("user-agent", user_agent);
+                add("accept-encoding", "gzip, deflate, br");
+                add("sec-fetch-dest", "document");
+            }
+            (Browser::Safari, _) => {
+                add("accept", SAFARI_ACCEPT);
+                add("user-agent", user_agent);
+                add("accept-language", "en-US,en;q=0.9");
+                add("accept-encoding", "gzip, deflate, br");
+            }
+            _ => {
+                add("user-agent", user_agent);
+                add("accept", "*/*");
+                add("accept-encoding", "gzip, deflate, br");
+                add("accept-language", "en-US,en;q=0.9");
+            }
+        }
+
+        Ok(Self {
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| Ok((name, HeaderValue::from_str(&value)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// headers for a random user agent
+    pub fn random() -> Self {
+        Self::for_user_agent(&get_random_usr_agent())
+            .expect("user agent lists hold valid header values")
+    }
+
+    /// replace the Accept-Language value, keeping its position
+    pub fn with_accept_language(mut self, value: HeaderValue) -> Self {
+        for (name, v) in self.headers.iter_mut() {
+            if *name == header::ACCEPT_LANGUAGE {
+                *v = value.clone();
+            }
+        }
+        self
+    }
+
+    pub fn user_agent(&self) -> Option<&HeaderValue> {
+        self.get(&header::USER_AGENT)
+    }
+
+    pub fn get(&self, name: &HeaderName) -> Option<&HeaderValue> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(HeaderName, HeaderValue)> {
+        self.headers.iter()
+    }
+
+    /// put these headers first, in order, followed by the headers only `headers` has
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let mut rest = std::mem::take(headers);
+        for (name, value) in self.headers.iter() {
+            rest.remove(name);
+            headers.insert(name.clone(), value.clone());
+        }
+        let mut last = None;
+        for (name, value) in rest {
+            if let Some(name) = name {
+                last = Some(name);
+            }
+            let name = last.clone().expect("first header always has a name");
+            headers.append(name, value);
+        }
+    }
+}
+
+fn platform_hint(os: Os) -> &'static str {
+    match os {
+        Os::Windows => "Windows",
+        Os::MacOs => "macOS",
+        Os::Linux => "Linux",
+        Os::ChromeOs => "Chrome OS",
+        Os::Android => "Android",
+        Os::Ios => "iOS",
+        Os::Other => "Unknown",
+    }
+}
+
+/// layer sending a full browser header set with every request
+///
+/// like `UserAgentLayer`, `random` draws a new browser per request and `sticky` keeps one
+/// for every service it wraps. headers the request already has and the set does not
+/// cover are kept after the set.
+#[derive(Debug, Clone)]
+pub struct BrowserHeadersLayer {
+    headers: Option<BrowserHeaders>,
+    rng: Option<SharedRng>,
+}
+
+impl BrowserHeadersLayer {
+    pub fn random() -> Self {
+        Self {
+            headers: None,
+            rng: None,
+        }
+    }
+
+    /// like `random` with every choice drawn from `seed`, see `UserAgentLayer::seeded`
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            headers: None,
+            rng: Some(SharedRng::from_seed(seed)),
+        }
+    }
+
+    pub fn sticky() -> Self {
+        Self::fixed(BrowserHeaders::random())
+    }
+
+    pub fn fixed(headers: BrowserHeaders) -> Self {
+        Self {
+            headers: Some(headers),
+            rng: None,
+        }
+    }
+
+    pub fn headers(&self) -> Option<&BrowserHeaders> {
+        self.headers.as_ref()
+    }
+}
+
+impl<S> Layer<S> for BrowserHeadersLayer {
+    type Service = BrowserHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BrowserHeadersService {
+            inner,
+            headers: self.headers.clone(),
+            rng: self.rng.clone(),
+        }
+    }
+}
+
+/// service built by `BrowserHeadersLayer`
+#[derive(Debug, Clone)]
+pub struct BrowserHeadersService<S> {
+    inner: S,
+    headers: Option<BrowserHeaders>,
+    rng: Option<SharedRng>,
+}
+
+impl<S, B> Service<Request<B>> for BrowserHeadersService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        match (&self.headers, &self.rng) {
+            (Some(headers), _) => headers.apply(req.headers_mut()),
+            (None, Some(rng)) => rng.scope(BrowserHeaders::random).apply(req.headers_mut()),
+            (None, None) => BrowserHeaders::random().apply(req.headers_mut()),
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    #[tokio::test]
+    async fn test_sticky_user_agent_layer() -> anyhow::Result<()> {
+        let echo = service_fn(|req: Request<()>| async move {
+            Ok::<_, std::convert::Infallible>(req.headers().get(header::USER_AGENT).cloned())
+        });
+        let layer = UserAgentLayer::sticky();
+        let expected = layer.agent().cloned();
+        let mut svc = ServiceBuilder::new().layer(layer).service(echo);
+        for _ in 0..5 {
+            let request = Request::builder()
+                .header(header::USER_AGENT, "curl/8.0")
+                .body(())?;
+            let agent = svc.ready().await?.call(request).await?;
+            assert_eq!(agent, expected);
+        }
+
+        let mut svc = ServiceBuilder::new()
+            .layer(UserAgentLayer::random())
+            .service(echo);
+        let agent = svc.ready().await?.call(Request::new(())).await?;
+        assert!(agent.is_some());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_seeded_layers_replay() -> anyhow::Result<()> {
+        let echo = service_fn(|req: Request<()>| async move {
+            Ok::<_, std::convert::Infallible>(req.headers().clone())
+        });
+        let run = |user_agents: UserAgentLayer, browsers: BrowserHeadersLayer| async move {
+            let mut sent = Vec::new();
+            for _ in 0..5 {
+                let mut svc = ServiceBuilder::new()
+                    .layer(user_agents.clone())
+                    .service(echo);
+                let headers = svc.ready().await?.call(Request::new(())).await?;
+                sent.push(headers.get(header::USER_AGENT).cloned());
+                // each request from another worker thread
+                let mut svc = ServiceBuilder::new().layer(browsers.clone()).service(echo);
+                let headers =
+                    tokio::spawn(async move { svc.ready().await?.call(Request::new(())).await })
+                        .await??;
+                sent.push(headers.get(header::USER_AGENT).cloned());
+            }
+            anyhow::Ok(sent)
+        };
+        let first = run(UserAgentLayer::seeded(3), BrowserHeadersLayer::seeded(4)).await?;
+        let replayed = run(UserAgentLayer::seeded(3), BrowserHeadersLayer::seeded(4)).await?;
+        assert_eq!(first, replayed);
+        assert!(first.iter().all(Option::is_some));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_browser_headers_match_user_agent() -> anyhow::Result<()> {
+        let chrome = BrowserHeaders::for_user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.64 Safari/537.36")?;
+        let names: Vec<_> = chrome.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            &names[..3],
+            ["sec-ch-ua", "sec-ch-ua-mobile", "sec-ch-ua-platform"]
+        );
+        assert_eq!(
+            chrome.get(&HeaderName::from_static("sec-ch-ua-platform")),
+            Some(&HeaderValue::from_static("\"macOS\""))
+        );
+
+        let firefox = BrowserHeaders::for_user_agent(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:100.0) Gecko/20100101 Firefox/100.0",
+        )?;
+        assert!(firefox.get(&HeaderName::from_static("sec-ch-ua")).is_none());
+        assert_eq!(
+            firefox.iter().next().map(|(n, _)| n),
+            Some(&header::USER_AGENT)
+        );
+
+        let echo = service_fn(|req: Request<()>| async move {
+            Ok::<_, std::convert::Infallible>(req.headers().clone())
+        });
+        let mut svc = ServiceBuilder::new()
+            .layer(BrowserHeadersLayer::fixed(firefox.clone()))
+            .service(echo);
+        let request = Request::builder()
+            .header(header::USER_AGENT, "curl/8.0")
+            .header("x-request-id", "1")
+            .body(())?;
+        let headers = svc.ready().await?.call(request).await?;
+        let sent: Vec<_> = headers
+            .iter()
+            .map(|(n, v)| (n.clone(), v.clone()))
+            .collect();
+        assert_eq!(
+            &sent[..sent.len() - 1],
+            firefox.iter().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            headers.get("x-request-id"),
+            Some(&HeaderValue::from_static("1"))
+        );
+        Ok(())
+    }
+}
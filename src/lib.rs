@@ -1,2 +1,7 @@
 pub mod quic;
 pub mod error;
+pub mod client;
+pub mod headers;
+pub mod tls;
+pub mod user_agent;
+pub mod utils;
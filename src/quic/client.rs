@@ -1,7 +1,29 @@
 use anyhow::Result;
+use rustls::{
+    client::{ResolvesClientCert, ServerCertVerifier, WebPkiVerifier},
+    sign::CertifiedKey,
+    ClientConfig, ConfigBuilder, WantsVerifier,
+};
 use s2n_quic::{client::Connect, stream::BidirectionalStream, Connection};
 
-use std::{net::SocketAddr, path::Path};
+use std::{
+    fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use super::common::{self, Passphrase, TlsProvider};
+use crate::tls::{
+    crl::{CrlServerVerifier, RevocationList},
+    keylog::{self, KeyLogging},
+    known_hosts::{KnownHosts, KnownHostsVerifier},
+    pinning::PinningVerifier,
+    signer::{KeySource, Signer},
+    trust::TrustStore,
+};
+use crate::utils::{get_random_int, random_seed, with_seed, TlsProfile};
 
 pub fn get_client(cert_pem_path: &Path) -> Result<s2n_quic::Client> {
     let client = s2n_quic::Client::builder()
@@ -11,20 +33,477 @@ pub fn get_client(cert_pem_path: &Path) -> Result<s2n_quic::Client> {
     Ok(client)
 }
 
-pub async fn client_connect_bidirectional(
+/// certificate and key presented when the server asks for client authentication
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    cert_path: PathBuf,
+    key: KeySource,
+}
+
+impl ClientIdentity {
+    pub fn new(cert_path: &Path, key_path: &Path) -> Self {
+        Self {
+            cert_path: cert_path.to_path_buf(),
+            key: KeySource::file(key_path),
+        }
+    }
+
+    /// identity whose handshake signatures are made by `signer`, rustls provider only
+    pub fn from_signer(cert_path: &Path, signer: Arc<dyn Signer>) -> Self {
+        Self {
+            cert_path: cert_path.to_path_buf(),
+            key: KeySource::Signer(signer),
+        }
+    }
+
+    /// ignored for identities backed by a signer
+    pub fn with_passphrase(mut self, passphrase: Passphrase) -> Self {
+        if let KeySource::File { passphrase: p, .. } = &mut self.key {
+            *p = Some(passphrase);
+        }
+        self
+    }
+
+    /// read the chain and key, failing early if they do not belong together
+    pub fn load(&self) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey)> {
+        let KeySource::File { path, passphrase } = &self.key else {
+            return Err(anyhow::anyhow!("the private key is held by a signer"));
+        };
+        let certs = common::read_cert_chain(&self.cert_path)?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect::<Vec<_>>();
+        let key = common::read_key_with_passphrase(path, passphrase.as_ref())?;
+        common::certified_key(certs.clone(), &key)?;
+        Ok((certs, key))
+    }
+
+    /// the chain paired with its key or signer
+    pub fn certified_key(&self) -> Result<CertifiedKey> {
+        self.key.certified_key(&self.cert_path)
+    }
+}
+
+struct IdentityResolver(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for IdentityResolver {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// rustls client config restricted to what QUIC allows
+pub(crate) fn quic_client_config() -> Result<ConfigBuilder<ClientConfig, WantsVerifier>> {
+    Ok(ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?)
+}
+
+/// rustls client config for QUIC using the TLS 1.3 part of `profile`
+pub(crate) fn quic_client_config_for(
+    profile: &TlsProfile,
+) -> Result<ConfigBuilder<ClientConfig, WantsVerifier>> {
+    let suites: Vec<_> = profile
+        .cipher_suites
+        .iter()
+        .copied()
+        .filter(|suite| suite.version() == &rustls::version::TLS13)
+        .collect();
+    if suites.is_empty() {
+        return Err(anyhow::anyhow!(
+            "QUIC needs TLS 1.3 but the profile has no TLS 1.3 cipher suites"
+        ));
+    }
+    Ok(ClientConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_kx_groups(&profile.kx_groups)
+        .with_protocol_versions(&[&rustls::version::TLS13])?)
+}
+
+pub(crate) fn start_client(
+    mut config: ClientConfig,
+    transport: Option<&TransportParams>,
+) -> Result<s2n_quic::Client> {
+    if config.alpn_protocols.is_empty() {
+        config.alpn_protocols = vec![common::QUIC_ALPN.to_vec()];
+    }
+    config.enable_early_data = true;
+    let tls = s2n_quic::provider::tls::rustls::Client::from(config);
+    let builder = s2n_quic::Client::builder().with_tls(tls)?;
+    let client = match transport {
+        Some(transport) => builder
+            .with_io(transport.io()?)?
+            .with_limits(transport.limits()?)?
+            .start()?,
+        None => builder.with_io("0.0.0.0:0")?.start()?,
+    };
+    Ok(client)
+}
+
+/// QUIC transport parameters a client announces
+///
+/// s2n-quic derives `max_udp_payload_size` from the path MTU, so it is set through the
+/// MTU of the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportParams {
+    /// `initial_max_data`
+    pub max_data: u64,
+    /// `initial_max_stream_data_bidi_local` and `_remote`
+    pub max_stream_data_bidi: u64,
+    /// `initial_max_stream_data_uni`
+    pub max_stream_data_uni: u64,
+    /// `initial_max_streams_bidi`
+    pub max_streams_bidi: u64,
+    /// `initial_max_streams_uni`, HTTP/3 needs at least 3
+    pub max_streams_uni: u64,
+    pub max_idle_timeout: Duration,
+    pub max_mtu: u16,
+}
+
+impl TransportParams {
+    /// random values within the ranges browsers and servers accept
+    pub fn random() -> Self {
+        let max_stream_data_bidi = get_random_int(256 * 1024, 6 * 1024 * 1024 + 1) as u64;
+        Self {
+            max_data: get_random_int(max_stream_data_bidi as usize, 16 * 1024 * 1024 + 1) as u64,
+            max_stream_data_bidi,
+            max_stream_data_uni: get_random_int(64 * 1024, 6 * 1024 * 1024 + 1) as u64,
+            max_streams_bidi: get_random_int(16, 129) as u64,
+            max_streams_uni: get_random_int(3, 104) as u64,
+            max_idle_timeout: Duration::from_secs(get_random_int(15, 61) as u64),
+            max_mtu: [1350, 1400, 1452, 1472, 1500][get_random_int(0, 5)],
+        }
+    }
+
+    pub(crate) fn limits(&self) -> Result<s2n_quic::provider::limits::Limits> {
+        Ok(s2n_quic::provider::limits::Limits::new()
+            .with_data_window(self.max_data)?
+            .with_bidirectional_local_data_window(self.max_stream_data_bidi)?
+            .with_bidirectional_remote_data_window(self.max_stream_data_bidi)?
+            .with_unidirectional_data_window(self.max_stream_data_uni)?
+            .with_max_open_remote_bidirectional_streams(self.max_streams_bidi)?
+            .with_max_open_remote_unidirectional_streams(self.max_streams_uni)?
+            .with_max_idle_timeout(self.max_idle_timeout)?)
+    }
+
+    pub(crate) fn io(&self) -> Result<s2n_quic::provider::io::tokio::Provider> {
+        Ok(s2n_quic::provider::io::tokio::Provider::builder()
+            .with_receive_address("0.0.0.0:0".parse()?)?
+            .with_max_mtu(self.max_mtu)?
+            .build()?)
+    }
+}
+
+/// tls settings for QUIC clients
+///
+/// verifiers, CRLs, webpki roots and in-memory certificates need the rustls provider,
+/// with s2n-tls the trust store is limited to CA files and the system roots.
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    provider: TlsProvider,
+    alpn_protocols: Vec<Vec<u8>>,
+    trust: TrustStore,
+    identity: Option<ClientIdentity>,
+    verifier: Option<Arc<dyn ServerCertVerifier>>,
+    crls: Option<Arc<RevocationList>>,
+    key_log: Option<KeyLogging>,
+    profile: Option<TlsProfile>,
+    transport: Option<TransportParams>,
+}
+
+impl fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("provider", &self.provider)
+            .field("alpn_protocols", &self.alpn_protocols)
+            .field("trust", &self.trust)
+            .field("identity", &self.identity)
+            .field("custom_verifier", &self.verifier.is_some())
+            .field("crls", &self.crls.is_some())
+            .field("key_log", &self.key_log)
+            .field("profile", &self.profile)
+            .field("transport", &self.transport)
+            .finish()
+    }
+}
+
+impl ClientTlsConfig {
+    pub fn new(trust: TrustStore) -> Self {
+        Self {
+            provider: TlsProvider::default(),
+            alpn_protocols: vec![common::QUIC_ALPN.to_vec()],
+            trust,
+            identity: None,
+            verifier: None,
+            crls: None,
+            key_log: None,
+            profile: None,
+            transport: None,
+        }
+    }
+
+    /// random TLS 1.3 profile and transport parameters, see `TlsProfile::random_tls13`
+    pub fn random(trust: TrustStore) -> Self {
+        Self::from_seed(trust, random_seed())
+    }
+
+    /// the profile and parameters `random` picked when it logged `seed`
+    pub fn from_seed(trust: TrustStore, seed: u64) -> Self {
+        let (profile, transport) = with_seed(seed, || {
+            (TlsProfile::random_tls13(), TransportParams::random())
+        });
+        Self::new(trust)
+            .with_profile(profile)
+            .with_transport_params(transport)
+    }
+
+    pub fn with_provider(mut self, provider: TlsProvider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// protocols offered in ALPN, `h3` by default
+    pub fn with_alpn_protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.alpn_protocols = protocols.into_iter().map(|p| p.as_ref().to_vec()).collect();
+        self
+    }
+
+    /// verify servers with `verifier` instead of the trust store
+    pub fn with_verifier(mut self, verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// only accept servers matching `pinning`
+    pub fn with_pinning(self, pinning: PinningVerifier) -> Self {
+        self.with_verifier(Arc::new(pinning))
+    }
+
+    /// reject servers whose certificate is listed in `crls`, cannot be combined with a custom
+    /// verifier
+    pub fn with_crls(mut self, crls: Arc<RevocationList>) -> Self {
+        self.crls = Some(crls);
+        self
+    }
+
+    /// trust each server's certificate on first use, see `KnownHosts`
+    ///
+    /// connect with `connect_known_host` to get a distinct error for changed certificates.
+    pub fn with_known_hosts(self, known_hosts: Arc<KnownHosts>) -> Self {
+        self.with_verifier(Arc::new(KnownHostsVerifier(known_hosts)))
+    }
+
+    pub fn with_identity(mut self, identity: ClientIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// write session secrets to `key_log` so captures can be decrypted, never in production
+    ///
+    /// s2n-tls only supports `KeyLogging::Env`.
+    pub fn with_key_log(mut self, key_log: KeyLogging) -> Self {
+        self.key_log = Some(key_log);
+        self
+    }
+
+    /// offer the TLS 1.3 suites and the groups of `profile`, rustls provider only
+    ///
+    /// ALPN still comes from `with_alpn_protocols`.
+    pub fn with_profile(mut self, profile: TlsProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn with_transport_params(mut self, transport: TransportParams) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn profile(&self) -> Option<&TlsProfile> {
+        self.profile.as_ref()
+    }
+
+    pub fn transport_params(&self) -> Option<&TransportParams> {
+        self.transport.as_ref()
+    }
+
+    pub fn rustls_config(&self) -> Result<ClientConfig> {
+        let verifier: Arc<dyn ServerCertVerifier> = match (&self.verifier, &self.crls) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "CRLs are checked by the default verifier and cannot be used with a custom one"
+                ))
+            }
+            (Some(verifier), None) => verifier.clone(),
+            (None, Some(crls)) => Arc::new(CrlServerVerifier::new(&self.trust, crls.clone())?),
+            (None, None) => Arc::new(WebPkiVerifier::new(self.trust.root_store()?, None)),
+        };
+        let builder = match self.profile.as_ref() {
+            Some(profile) => quic_client_config_for(profile)?,
+            None => quic_client_config()?,
+        };
+        let builder = builder.with_custom_certificate_verifier(verifier);
+        let mut config = match self.identity.as_ref() {
+            Some(identity) => builder.with_client_cert_resolver(Arc::new(IdentityResolver(
+                Arc::new(identity.certified_key()?),
+            ))),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols.clone();
+        if let Some(key_log) = self.key_log.as_ref() {
+            config.key_log = key_log.open()?;
+        }
+        Ok(config)
+    }
+
+    pub fn start(&self) -> Result<s2n_quic::Client> {
+        match self.provider {
+            TlsProvider::Rustls => start_client(self.rustls_config()?, self.transport.as_ref()),
+            TlsProvider::S2nTls => self.start_s2n_tls(),
+        }
+    }
+
+    fn start_s2n_tls(&self) -> Result<s2n_quic::Client> {
+        use s2n_quic::provider::tls::s2n_tls;
+
+        if self.verifier.is_some() || self.crls.is_some() || self.profile.is_some() {
+            return Err(anyhow::anyhow!(
+                "custom verifiers, CRLs and TLS profiles need the rustls provider"
+            ));
+        }
+        if self.trust.uses_webpki_roots() || !self.trust.certificates().is_empty() {
+            return Err(anyhow::anyhow!(
+                "s2n-tls only trusts CA files and the system roots"
+            ));
+        }
+        let mut builder = s2n_tls::Client::builder();
+        if !self.trust.uses_system_roots() {
+            builder = builder.with_empty_trust_store()?;
+        }
+        for path in self.trust.ca_files() {
+            builder = builder.with_certificate(path.as_path())?;
+        }
+        if let Some(identity) = self.identity.as_ref() {
+            let KeySource::File {
+                path,
+                passphrase: None,
+            } = &identity.key
+            else {
+                return Err(anyhow::anyhow!(
+                    "encrypted client keys and signers need the rustls provider"
+                ));
+            };
+            builder = builder.with_client_identity(identity.cert_path.as_path(), path.as_path())?;
+        }
+        if let Some(key_log) = self.key_log.as_ref() {
+            keylog::warn_enabled(&key_log.s2n_path()?);
+            builder = builder.with_key_logging()?;
+        }
+        let tls = builder
+            .with_application_protocols(self.alpn_protocols.iter())?
+            .build()?;
+        let builder = s2n_quic::Client::builder().with_tls(tls)?;
+        let client = match self.transport.as_ref() {
+            Some(transport) => builder
+                .with_io(transport.io()?)?
+                .with_limits(transport.limits()?)?
+                .start()?,
+            None => builder.with_io("0.0.0.0:0")?.start()?,
+        };
+        Ok(client)
+    }
+}
+
+/// client trusting the roots in `trust` instead of a single pem file
+pub fn get_client_with_trust(trust: &TrustStore) -> Result<s2n_quic::Client> {
+    ClientTlsConfig::new(trust.clone()).start()
+}
+
+/// like `get_client` with an explicit choice of TLS provider
+pub fn get_client_with_provider(
+    cert_pem_path: &Path,
+    provider: TlsProvider,
+) -> Result<s2n_quic::Client> {
+    ClientTlsConfig::new(TrustStore::from_ca_file(cert_pem_path))
+        .with_provider(provider)
+        .start()
+}
+
+/// like `get_client` with a random TLS profile and transport parameters
+///
+/// the returned config holds the values used, for logging.
+pub fn get_random_client(cert_pem_path: &Path) -> Result<(s2n_quic::Client, ClientTlsConfig)> {
+    let config = ClientTlsConfig::random(TrustStore::from_ca_file(cert_pem_path));
+    tracing::debug!(
+        "random QUIC client profile {:?} transport {:?}",
+        config.profile(),
+        config.transport_params()
+    );
+    Ok((config.start()?, config))
+}
+
+/// client for servers that require mutual TLS, the identity key may be encrypted
+pub fn get_client_with_identity(
+    cert_pem_path: &Path,
+    identity: &ClientIdentity,
+) -> Result<s2n_quic::Client> {
+    ClientTlsConfig::new(TrustStore::from_ca_file(cert_pem_path))
+        .with_identity(identity.clone())
+        .start()
+}
+
+/// connect an existing client to `addr`
+pub async fn connect(
+    client: &s2n_quic::Client,
     addr: SocketAddr,
     server_name: &str,
-    cert_pem_path: &Path,
     keep_alive: bool,
-) -> Result<(s2n_quic::Client, BidirectionalStream)> {
-    let client = get_client(cert_pem_path)?;
-
+) -> Result<Connection> {
     let connect = Connect::new(addr).with_server_name(server_name);
     let mut connection = client.connect(connect).await?;
 
     // ensure the connection doesn't time out with inactivity
     connection.keep_alive(keep_alive)?;
 
+    Ok(connection)
+}
+
+/// like `connect` for a client using `with_known_hosts`, a changed server certificate
+/// fails with `NetworkError::HostCertificateChanged`
+pub async fn connect_known_host(
+    client: &s2n_quic::Client,
+    known_hosts: &KnownHosts,
+    addr: SocketAddr,
+    server_name: &str,
+    keep_alive: bool,
+) -> Result<Connection> {
+    connect(client, addr, server_name, keep_alive)
+        .await
+        .map_err(|e| known_hosts.host_error(server_name, e))
+}
+
+pub async fn client_connect_bidirectional(
+    addr: SocketAddr,
+    server_name: &str,
+    cert_pem_path: &Path,
+    keep_alive: bool,
+) -> Result<(s2n_quic::Client, BidirectionalStream)> {
+    let client = get_client(cert_pem_path)?;
+    let mut connection = connect(&client, addr, server_name, keep_alive).await?;
+
     // open a new stream
     let stream = connection.open_bidirectional_stream().await?;
 
@@ -38,12 +517,7 @@ pub async fn client_connect(
     keep_alive: bool,
 ) -> Result<(s2n_quic::Client, Connection)> {
     let client = get_client(cert_pem_path)?;
-
-    let connect = Connect::new(addr).with_server_name(server_name);
-    let mut connection = client.connect(connect).await?;
-
-    // ensure the connection doesn't time out with inactivity
-    connection.keep_alive(keep_alive)?;
+    let connection = connect(&client, addr, server_name, keep_alive).await?;
 
     Ok::<_, anyhow::Error>((client, connection))
 }
@@ -0,0 +1,328 @@
+use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+
+use super::common::{self, CertificateBuilder};
+
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// validity of a single certificate in a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertExpiry {
+    pub subject: String,
+    pub not_after: SystemTime,
+}
+
+impl CertExpiry {
+    /// time left before `not_after`, `None` once the certificate has expired
+    pub fn remaining(&self) -> Option<Duration> {
+        self.not_after.duration_since(SystemTime::now()).ok()
+    }
+}
+
+/// parse `notAfter` for every certificate in a chain read by `read_cert_chain`
+pub fn inspect_chain(chain: &[Vec<u8>]) -> Result<Vec<CertExpiry>> {
+    chain
+        .iter()
+        .map(|der| {
+            let (_, cert) = x509_parser::parse_x509_certificate(der)
+                .map_err(|e| anyhow::anyhow!("failed to parse certificate: {}", e))?;
+            let not_after = cert.validity().not_after.timestamp();
+            let not_after = UNIX_EPOCH + Duration::from_secs(not_after.max(0) as u64);
+            Ok(CertExpiry {
+                subject: cert.subject().to_string(),
+                not_after,
+            })
+        })
+        .collect()
+}
+
+/// the certificate in the chain that expires first
+pub fn earliest_expiry(chain: &[Vec<u8>]) -> Result<CertExpiry> {
+    inspect_chain(chain)?
+        .into_iter()
+        .min_by_key(|c| c.not_after)
+        .ok_or_else(|| anyhow::anyhow!("certificate chain is empty"))
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpiryEvent {
+    /// `remaining` dropped below `threshold`
+    Threshold {
+        expiry: CertExpiry,
+        remaining: Duration,
+        threshold: Duration,
+    },
+    Expired {
+        expiry: CertExpiry,
+    },
+    Renewed {
+        expiry: CertExpiry,
+    },
+    RenewalFailed {
+        error: String,
+    },
+}
+
+/// how to replace a certificate ahead of expiry
+///
+/// the builder's `cert_path`/`key_path` should point at the monitored files, and its
+/// `validity` is measured from the time of renewal.
+#[derive(Clone)]
+pub enum Renewal {
+    SelfSigned(CertificateBuilder),
+    SignedBy {
+        builder: CertificateBuilder,
+        issuer: Arc<rcgen::Certificate>,
+    },
+}
+
+impl Renewal {
+    fn renew(&self) -> Result<()> {
+        match self {
+            Renewal::SelfSigned(builder) => builder.generate()?,
+            Renewal::SignedBy { builder, issuer } => builder.generate_signed_by(issuer)?,
+        };
+        Ok(())
+    }
+}
+
+type ExpiryCallback = Arc<dyn Fn(&ExpiryEvent) + Send + Sync>;
+
+/// counters updated by every `ExpiryMonitor::check`, shared with the spawned task
+#[derive(Debug, Default)]
+pub struct ExpiryMetrics {
+    checks: AtomicU64,
+    seconds_remaining: AtomicU64,
+    thresholds_crossed: AtomicU64,
+    expired: AtomicU64,
+    renewals: AtomicU64,
+    renewal_failures: AtomicU64,
+}
+
+impl ExpiryMetrics {
+    pub fn checks(&self) -> u64 {
+        self.checks.load(Ordering::Relaxed)
+    }
+
+    /// time left on the earliest expiring certificate at the last check, 0 once expired
+    pub fn seconds_remaining(&self) -> u64 {
+        self.seconds_remaining.load(Ordering::Relaxed)
+    }
+
+    pub fn thresholds_crossed(&self) -> u64 {
+        self.thresholds_crossed.load(Ordering::Relaxed)
+    }
+
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    pub fn renewals(&self) -> u64 {
+        self.renewals.load(Ordering::Relaxed)
+    }
+
+    pub fn renewal_failures(&self) -> u64 {
+        self.renewal_failures.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, event: &ExpiryEvent) {
+        let counter = match event {
+            ExpiryEvent::Threshold { .. } => &self.thresholds_crossed,
+            ExpiryEvent::Expired { .. } => &self.expired,
+            ExpiryEvent::Renewed { .. } => &self.renewals,
+            ExpiryEvent::RenewalFailed { .. } => &self.renewal_failures,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// periodically checks a certificate file and warns as it approaches `notAfter`
+///
+/// threshold and expiry events are emitted once for a given certificate, counts are
+/// kept in `ExpiryMonitor::metrics`.
+pub struct ExpiryMonitor {
+    cert_path: PathBuf,
+    thresholds: Vec<Duration>,
+    check_interval: Duration,
+    callback: Option<ExpiryCallback>,
+    renewal: Option<(Renewal, Duration)>,
+    metrics: Arc<ExpiryMetrics>,
+    // smallest threshold already reported for the certificate expiring at the given time
+    reported: Option<(SystemTime, Duration)>,
+    // `notAfter` of the certificate already reported as expired
+    reported_expired: Option<SystemTime>,
+}
+
+impl ExpiryMonitor {
+    pub fn new(cert_path: &Path) -> Self {
+        Self {
+            cert_path: cert_path.to_path_buf(),
+            thresholds: vec![DAY * 30, DAY * 7, DAY],
+            check_interval: Duration::from_secs(60 * 60),
+            callback: None,
+            renewal: None,
+            metrics: Arc::default(),
+            reported: None,
+            reported_expired: None,
+        }
+    }
+
+    pub fn thresholds(mut self, thresholds: Vec<Duration>) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&ExpiryEvent) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// regenerate the certificate once less than `renew_before` remains
+    pub fn renew_with(mut self, renewal: Renewal, renew_before: Duration) -> Self {
+        self.renewal = Some((renewal, renew_before));
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<ExpiryMetrics> {
+        self.metrics.clone()
+    }
+
+    /// run a single check, returning the events it produced
+    pub fn check(&mut self) -> Result<Vec<ExpiryEvent>> {
+        let mut expiry = earliest_expiry(&common::read_cert_chain(&self.cert_path)?)?;
+        let mut events = Vec::new();
+        if let Some((renewal, renew_before)) = self.renewal.as_ref() {
+            let due = match expiry.remaining() {
+                Some(remaining) => remaining <= *renew_before,
+                None => true,
+            };
+            if due {
+                let event = self.renew(renewal);
+                // thresholds apply to whichever certificate is now on disk
+                if let ExpiryEvent::Renewed { expiry: renewed } = &event {
+                    expiry = renewed.clone();
+                }
+                events.push(event);
+            }
+        }
+
+        let remaining = expiry.remaining();
+        let seconds_remaining = remaining.map(|r| r.as_secs()).unwrap_or(0);
+        self.metrics.checks.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .seconds_remaining
+            .store(seconds_remaining, Ordering::Relaxed);
+        tracing::debug!(
+            cert = %self.cert_path.display(),
+            subject = %expiry.subject,
+            seconds_remaining,
+            "checked certificate expiry"
+        );
+
+        match remaining {
+            None if self.reported_expired == Some(expiry.not_after) => {}
+            None => {
+                tracing::error!(
+                    cert = %self.cert_path.display(),
+                    subject = %expiry.subject,
+                    seconds_remaining = 0u64,
+                    "certificate has expired"
+                );
+                self.reported_expired = Some(expiry.not_after);
+                events.push(ExpiryEvent::Expired { expiry });
+            }
+            Some(remaining) => {
+                let already = match self.reported {
+                    Some((not_after, threshold)) if not_after == expiry.not_after => threshold,
+                    _ => Duration::MAX,
+                };
+                let crossed = self
+                    .thresholds
+                    .iter()
+                    .copied()
+                    .filter(|t| remaining <= *t && *t < already)
+                    .min();
+                if let Some(threshold) = crossed {
+                    tracing::warn!(
+                        cert = %self.cert_path.display(),
+                        subject = %expiry.subject,
+                        seconds_remaining = remaining.as_secs(),
+                        threshold_secs = threshold.as_secs(),
+                        "certificate expires soon"
+                    );
+                    self.reported = Some((expiry.not_after, threshold));
+                    events.push(ExpiryEvent::Threshold {
+                        expiry,
+                        remaining,
+                        threshold,
+                    });
+                }
+            }
+        }
+        self.emit(&events);
+        Ok(events)
+    }
+
+    fn renew(&self, renewal: &Renewal) -> ExpiryEvent {
+        tracing::info!(cert = %self.cert_path.display(), "renewing certificate");
+        let renewed = renewal
+            .renew()
+            .and_then(|_| earliest_expiry(&common::read_cert_chain(&self.cert_path)?));
+        match renewed {
+            Ok(expiry) => ExpiryEvent::Renewed { expiry },
+            Err(e) => {
+                tracing::error!(
+                    cert = %self.cert_path.display(),
+                    "certificate renewal failed {:?}",
+                    e
+                );
+                ExpiryEvent::RenewalFailed {
+                    error: e.to_string(),
+                }
+            }
+        }
+    }
+
+    fn emit(&self, events: &[ExpiryEvent]) {
+        for event in events {
+            self.metrics.record(event);
+            if let Some(callback) = self.callback.as_ref() {
+                callback(event);
+            }
+        }
+    }
+
+    /// run `check` every `check_interval` on a background task
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.check_interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.check() {
+                    tracing::error!(
+                        cert = %self.cert_path.display(),
+                        "expiry check failed {:?}",
+                        e
+                    );
+                }
+            }
+        })
+    }
+}
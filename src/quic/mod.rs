@@ -1,6 +1,9 @@
 pub mod client;
 pub mod common;
+pub mod expiry;
+pub mod reload;
 pub mod server;
+pub mod sni;
 
 #[cfg(test)]
 mod test {
@@ -8,7 +11,15 @@ mod test {
     use anyhow::Result;
     use bytes::Bytes;
     use s2n_quic::{stream::BidirectionalStream, Connection};
-    use std::{net::SocketAddr, path::Path};
+    use std::{net::SocketAddr, path::PathBuf};
+    use tempfile::TempDir;
+
+    fn test_cert_paths(dir: &TempDir, name: &str) -> (PathBuf, PathBuf) {
+        (
+            dir.path().join(format!("{name}_cert.pem")),
+            dir.path().join(format!("{name}_key.pem")),
+        )
+    }
 
     async fn server_handle_request(stream: BidirectionalStream) -> Result<()> {
         let mut stream = stream;
@@ -30,24 +41,22 @@ mod test {
 
     #[tokio::test]
     async fn test_client_server() -> anyhow::Result<()> {
-        common::generate_self_signed(vec!["localhost".to_string()], None, None)?;
-        let addr: SocketAddr = "127.0.0.1:4444".parse()?;
-
-        tokio::spawn(async move {
-            let _ = server::run_server(
-                Path::new("cert.pem"),
-                Path::new("key.pem"),
-                addr,
-                server_handle_conn,
-            )
-            .await;
-        });
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::generate_self_signed(
+            vec!["localhost".to_string()],
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        )?;
+        let server = server::get_server(&cert_path, &key_path, "127.0.0.1:0".parse()?)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(server::serve(server, server_handle_conn));
 
         let (_, stream) =
-            client::client_connect_bidirectional(addr, "localhost", Path::new("cert.pem"), true)
+            client::client_connect_bidirectional(addr, "localhost", &cert_path, true)
                 .await?;
         let (mut receive_stream, mut send_stream) = stream.split();
-        let test_data = vec![
+        let test_data = [
             "hello".to_string(),
             "world".to_string(),
             "foo".to_string(),
@@ -63,24 +72,22 @@ mod test {
     }
     #[tokio::test]
     async fn test_bidirectional_client_server() -> anyhow::Result<()> {
-        common::generate_self_signed(vec!["localhost".to_string()], None, None)?;
-        let addr: SocketAddr = "127.0.0.1:4433".parse()?;
-
-        tokio::spawn(async move {
-            let _ = server::run_bidirectional_server(
-                Path::new("cert.pem"),
-                Path::new("key.pem"),
-                addr,
-                server_handle_request,
-            )
-            .await;
-        });
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::generate_self_signed(
+            vec!["localhost".to_string()],
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        )?;
+        let server = server::get_server(&cert_path, &key_path, "127.0.0.1:0".parse()?)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(server::serve_bidirectional(server, server_handle_request));
 
         let (_, stream) =
-            client::client_connect_bidirectional(addr, "localhost", Path::new("cert.pem"), true)
+            client::client_connect_bidirectional(addr, "localhost", &cert_path, true)
                 .await?;
         let (mut receive_stream, mut send_stream) = stream.split();
-        let test_data = vec![
+        let test_data = [
             "hello".to_string(),
             "world".to_string(),
             "foo".to_string(),
@@ -94,4 +101,523 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_certificate_builder() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .dns_name("127.0.0.1")
+            .wildcard_name("example.com")
+            .common_name("quic-hyper-stunt test")
+            .key_algorithm(common::KeyAlgorithm::EcdsaP384)
+            .validity(std::time::Duration::from_secs(60 * 60))
+            .cert_path(&cert_path)
+            .key_path(&key_path)
+            .generate()?;
+
+        assert_eq!(common::read_cert_chain(&cert_path)?, vec![cert.0]);
+        assert_eq!(common::read_key(&key_path)?, key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&key_path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_keeps_current_pair_on_mismatch() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let (other_cert_path, _) = test_cert_paths(&dir, "other");
+        let builder = common::CertificateBuilder::new().dns_name("localhost");
+        builder.clone().cert_path(&cert_path).key_path(&key_path).generate()?;
+        let resolver = reload::ReloadingCertResolver::new(&cert_path, &key_path)?;
+        let original = resolver.current();
+
+        // a cert without its matching key is rejected
+        builder.clone().cert_path(&other_cert_path).generate()?;
+        std::fs::copy(&other_cert_path, &cert_path)?;
+        assert!(resolver.reload().is_err());
+        assert!(std::sync::Arc::ptr_eq(&original, &resolver.current()));
+
+        builder.cert_path(&cert_path).key_path(&key_path).generate()?;
+        resolver.reload()?;
+        assert_eq!(
+            *resolver.subscribe().borrow(),
+            reload::ReloadStatus::Loaded { generation: 1 }
+        );
+        Ok(())
+    }
+
+    fn ocsp_response_for(
+        cert: &rustls::Certificate,
+        next_update: std::time::SystemTime,
+    ) -> anyhow::Result<Vec<u8>> {
+        use crate::tls::ocsp::*;
+        use der::{
+            asn1::{Any, BitString, GeneralizedTime, Int, ObjectIdentifier, OctetString},
+            Encode, Tag, TagNumber,
+        };
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)?;
+        let sha1 = |data: &[u8]| {
+            OctetString::new(
+                ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, data).as_ref(),
+            )
+        };
+        let now = GeneralizedTime::from_system_time(std::time::SystemTime::now())?;
+        let single = SingleResponse {
+            cert_id: CertId {
+                hash_algorithm: AlgorithmIdentifier {
+                    algorithm: ObjectIdentifier::new_unwrap("1.3.14.3.2.26"),
+                    parameters: Some(Any::null()),
+                },
+                issuer_name_hash: sha1(parsed.issuer().as_raw())?,
+                // self-signed, the leaf is its own issuer
+                issuer_key_hash: sha1(&parsed.public_key().subject_public_key.data)?,
+                serial_number: Int::new(parsed.tbs_certificate.raw_serial())?,
+            },
+            cert_status: Any::new(
+                Tag::ContextSpecific {
+                    constructed: false,
+                    number: TagNumber::N0,
+                },
+                Vec::new(),
+            )?,
+            this_update: now,
+            next_update: Some(GeneralizedTime::from_system_time(next_update)?),
+            single_extensions: None,
+        };
+        let basic = BasicOcspResponse {
+            tbs_response_data: ResponseData {
+                version: None,
+                responder_id: Any::new(
+                    Tag::ContextSpecific {
+                        constructed: true,
+                        number: TagNumber::N2,
+                    },
+                    OctetString::new([0u8; 20])?.to_der()?,
+                )?,
+                produced_at: now,
+                responses: vec![single],
+                response_extensions: None,
+            },
+            signature_algorithm: AlgorithmIdentifier {
+                algorithm: ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2"),
+                parameters: None,
+            },
+            signature: BitString::from_bytes(&[0])?,
+            certs: None,
+        };
+        Ok(OcspResponseAsn1 {
+            response_status: ResponseStatus::Successful,
+            response_bytes: Some(ResponseBytes {
+                response_type: ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.48.1.1"),
+                response: OctetString::new(basic.to_der()?)?,
+            }),
+        }
+        .to_der()?)
+    }
+
+    #[test]
+    fn test_reload_staples_ocsp_response() -> anyhow::Result<()> {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let ocsp_path = dir.path().join("ocsp.der");
+        let builder = common::CertificateBuilder::new().dns_name("localhost");
+        let (cert, _) = builder
+            .clone()
+            .cert_path(&cert_path)
+            .key_path(&key_path)
+            .generate()?;
+        let (other, _) = builder.generate()?;
+
+        let expired = SystemTime::now() - Duration::from_secs(60);
+        let der = ocsp_response_for(&cert, expired)?;
+        std::fs::write(&ocsp_path, &der)?;
+        let resolver =
+            reload::ReloadingCertResolver::new_with_ocsp(&cert_path, &key_path, None, &ocsp_path)?;
+        assert_eq!(resolver.current().ocsp.as_deref(), Some(der.as_slice()));
+        let response = resolver.ocsp_response().expect("ocsp response");
+        assert_eq!(response.status, crate::tls::ocsp::OcspStatus::Good);
+        assert!(response.is_stale());
+
+        // a response for another certificate is never stapled
+        std::fs::write(&ocsp_path, ocsp_response_for(&other, expired)?)?;
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.current().ocsp.as_deref(), Some(der.as_slice()));
+        assert!(reload::ReloadingCertResolver::new_with_ocsp(
+            &cert_path, &key_path, None, &ocsp_path
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sni_host_matching() {
+        let mut hosts = sni::HostMap::default();
+        hosts.insert("api.example.com", 1);
+        hosts.insert("*.example.com", 2);
+        assert_eq!(hosts.get(Some("API.example.com.")), Some(&1));
+        assert_eq!(hosts.get(Some("www.example.com")), Some(&2));
+        assert_eq!(hosts.get(Some("a.b.example.com")), None);
+        assert_eq!(hosts.get(None), None);
+        hosts.default = Some(3);
+        assert_eq!(hosts.get(Some("other.org")), Some(&3));
+        assert_eq!(hosts.get(None), Some(&3));
+    }
+
+    #[test]
+    fn test_provider_configs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .cert_path(&cert_path)
+            .key_path(&key_path)
+            .generate()?;
+        let config = server::ServerTlsConfig::new(&cert_path, &key_path)
+            .with_client_ca(&cert_path)
+            .with_alpn_protocols(["h3", "hq-interop"])
+            .rustls_config()?;
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h3".to_vec(), b"hq-interop".to_vec()]
+        );
+
+        // webpki roots only exist as rustls trust anchors
+        let client =
+            client::ClientTlsConfig::new(crate::tls::trust::TrustStore::new().with_webpki_roots())
+                .with_provider(common::TlsProvider::S2nTls);
+        assert!(client.start().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_server_s2n_tls() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::generate_self_signed(
+            vec!["localhost".to_string()],
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        )?;
+        let server = server::ServerTlsConfig::new(&cert_path, &key_path)
+            .with_provider(common::TlsProvider::S2nTls)
+            .start("127.0.0.1:0".parse()?)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(server::serve(server, server_handle_conn));
+
+        let client =
+            client::ClientTlsConfig::new(crate::tls::trust::TrustStore::from_ca_file(&cert_path))
+                .with_provider(common::TlsProvider::S2nTls)
+                .start()?;
+        let mut connection = client::connect(&client, addr, "localhost", true).await?;
+        let stream = connection.open_bidirectional_stream().await?;
+        let (mut receive_stream, mut send_stream) = stream.split();
+        let data = Bytes::from("hello");
+        send_stream.send(data.clone()).await?;
+        assert_eq!(receive_stream.receive().await?, Some(data));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_s2n_tls_rejects_rustls_only_options() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::generate_self_signed(
+            vec!["localhost".to_string()],
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        )?;
+        let addr: SocketAddr = "127.0.0.1:0".parse()?;
+        let config = server::ServerTlsConfig::new(&cert_path, &key_path)
+            .with_provider(common::TlsProvider::S2nTls);
+
+        let err = config
+            .clone()
+            .with_passphrase(common::Passphrase::Value("secret".to_string()))
+            .start(addr)
+            .expect_err("encrypted keys need rustls");
+        assert!(err.to_string().contains("encrypted keys"));
+        let config = config.with_reload(std::time::Duration::from_secs(1));
+        let err = config
+            .clone()
+            .start(addr)
+            .expect_err("reloading needs rustls");
+        assert!(err.to_string().contains("reloading"));
+        config
+            .with_provider(common::TlsProvider::Rustls)
+            .rustls_config()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_server_random_clients() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        common::generate_self_signed(
+            vec!["localhost".to_string()],
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        )?;
+        let server = server::get_server(&cert_path, &key_path, "127.0.0.1:0".parse()?)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(server::serve(server, server_handle_conn));
+
+        let (random, _) = client::get_random_client(&cert_path)?;
+        let mut clients = vec![random];
+        for seed in 0..4 {
+            let trust = crate::tls::trust::TrustStore::from_ca_file(&cert_path);
+            clients.push(client::ClientTlsConfig::from_seed(trust, seed).start()?);
+        }
+        for client in clients {
+            let mut connection = client::connect(&client, addr, "localhost", true).await?;
+            let stream = connection.open_bidirectional_stream().await?;
+            let (mut receive_stream, mut send_stream) = stream.split();
+            let data = Bytes::from("hello");
+            send_stream.send(data.clone()).await?;
+            assert_eq!(receive_stream.receive().await?, Some(data));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_client_is_tls13_only() -> anyhow::Result<()> {
+        use crate::{tls::trust::TrustStore, utils::TlsProfile};
+
+        let trust = TrustStore::new().with_webpki_roots();
+        for _ in 0..20 {
+            let client = client::ClientTlsConfig::random(trust.clone());
+            client.rustls_config()?;
+            let profile = client.profile().expect("random sets a profile");
+            assert!(profile
+                .cipher_suites
+                .iter()
+                .all(|suite| suite.version() == &rustls::version::TLS13));
+            let transport = client.transport_params().expect("random sets transport");
+            assert!(transport.max_data >= transport.max_stream_data_bidi);
+            assert!(transport.max_streams_uni >= 3);
+        }
+        let seeded = client::ClientTlsConfig::from_seed(trust.clone(), 9);
+        let replayed = client::ClientTlsConfig::from_seed(trust.clone(), 9);
+        assert_eq!(seeded.transport_params(), replayed.transport_params());
+
+        let tls12 = TlsProfile::new(
+            vec![rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256],
+            rustls::ALL_KX_GROUPS.to_vec(),
+            vec![&rustls::version::TLS12],
+        );
+        assert!(client::ClientTlsConfig::new(trust)
+            .with_profile(tls12)
+            .rustls_config()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiry_monitor_renews() -> anyhow::Result<()> {
+        use std::time::Duration;
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let builder = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .validity(Duration::from_secs(60 * 60))
+            .cert_path(&cert_path)
+            .key_path(&key_path);
+        builder.generate()?;
+
+        let mut monitor = expiry::ExpiryMonitor::new(&cert_path).thresholds(vec![
+            Duration::from_secs(2 * 60 * 60),
+            Duration::from_secs(60),
+        ]);
+        let events = monitor.check()?;
+        assert!(matches!(
+            events[..],
+            [expiry::ExpiryEvent::Threshold { .. }]
+        ));
+        // each threshold is only reported once
+        assert!(monitor.check()?.is_empty());
+
+        let renewal =
+            expiry::Renewal::SelfSigned(builder.validity(Duration::from_secs(90 * 24 * 60 * 60)));
+        let mut monitor = monitor.renew_with(renewal, Duration::from_secs(24 * 60 * 60));
+        let events = monitor.check()?;
+        assert!(matches!(events[..], [expiry::ExpiryEvent::Renewed { .. }]));
+        assert!(monitor.check()?.is_empty());
+        let metrics = monitor.metrics();
+        assert_eq!(metrics.checks(), 4);
+        assert_eq!(metrics.thresholds_crossed(), 1);
+        assert_eq!(metrics.renewals(), 1);
+        assert!(metrics.seconds_remaining() > 89 * 24 * 60 * 60);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiry_monitor_reports_once() -> anyhow::Result<()> {
+        use std::time::{Duration, SystemTime};
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let day = Duration::from_secs(24 * 60 * 60);
+        common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .not_before(SystemTime::now() - day * 2)
+            .not_after(SystemTime::now() - day)
+            .cert_path(&cert_path)
+            .key_path(&key_path)
+            .generate()?;
+
+        let mut monitor = expiry::ExpiryMonitor::new(&cert_path);
+        assert!(matches!(
+            monitor.check()?[..],
+            [expiry::ExpiryEvent::Expired { .. }]
+        ));
+        assert!(monitor.check()?.is_empty());
+        assert_eq!(monitor.metrics().expired(), 1);
+
+        // a renewed certificate still inside a threshold reports it in the same check
+        let renewal = expiry::Renewal::SelfSigned(
+            common::CertificateBuilder::new()
+                .dns_name("localhost")
+                .validity(day * 10)
+                .cert_path(&cert_path)
+                .key_path(&key_path),
+        );
+        let mut monitor = monitor.renew_with(renewal, day * 5);
+        assert!(matches!(
+            monitor.check()?[..],
+            [
+                expiry::ExpiryEvent::Renewed { .. },
+                expiry::ExpiryEvent::Threshold { .. }
+            ]
+        ));
+        assert!(monitor.check()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_der_and_combined_pem() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .cert_path(&cert_path)
+            .key_path(&key_path)
+            .generate()?;
+
+        let (der_cert_path, der_key_path) =
+            (dir.path().join("cert.crt"), dir.path().join("key.der"));
+        std::fs::write(&der_cert_path, &cert.0)?;
+        std::fs::write(&der_key_path, &key.0)?;
+        assert_eq!(
+            common::read_cert_chain(&der_cert_path)?,
+            vec![cert.0.clone()]
+        );
+        assert_eq!(common::read_key(&der_key_path)?, key);
+
+        let combined_path = dir.path().join("combined.pem");
+        let combined = std::fs::read_to_string(&cert_path)? + &std::fs::read_to_string(&key_path)?;
+        std::fs::write(&combined_path, combined)?;
+        assert_eq!(
+            common::read_identity(&combined_path, None)?,
+            (vec![cert], key)
+        );
+
+        // a certificate is not a key, the error names the detected format
+        let err = common::read_key(&der_cert_path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::NetworkError>(),
+            Some(crate::error::NetworkError::FormatError(
+                common::FileFormat::Der,
+                _
+            ))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_pkcs12_bundle() -> anyhow::Result<()> {
+        use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let mut store = KeyStore::new();
+        let chain = PrivateKeyChain::new(
+            &key.0,
+            b"test_pkcs12",
+            [p12_keystore::Certificate::from_der(&cert.0)?],
+        );
+        store.add_entry("localhost", KeyStoreEntry::PrivateKeyChain(chain));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bundle.p12");
+        std::fs::write(&path, store.writer("secret").write()?)?;
+        let passphrase = common::Passphrase::Value("secret".to_string());
+        assert_eq!(
+            common::read_identity(&path, Some(&passphrase))?,
+            (vec![cert.clone()], key.clone())
+        );
+        assert_eq!(
+            common::read_key_with_passphrase(&path, Some(&passphrase))?,
+            key
+        );
+
+        let wrong = common::Passphrase::Value("wrong".to_string());
+        let err = common::read_identity(&path, Some(&wrong)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::NetworkError>(),
+            Some(crate::error::NetworkError::FormatError(
+                common::FileFormat::Pkcs12,
+                _
+            ))
+        ));
+
+        // an unprotected bundle also works where no passphrase can be given
+        let pfx_path = dir.path().join("bundle.pfx");
+        std::fs::write(&pfx_path, store.writer("").write()?)?;
+        assert_eq!(common::read_cert_chain(&pfx_path)?, vec![cert.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_encrypted_key() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert_path, key_path) = test_cert_paths(&dir, "server");
+        let (_, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .cert_path(&cert_path)
+            .generate()?;
+        let params = pkcs8::pkcs5::pbes2::Parameters::pbkdf2_sha256_aes256cbc(
+            2048,
+            b"saltsalt",
+            b"0123456789abcdef",
+        )
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let encrypted = pkcs8::PrivateKeyInfo::try_from(key.0.as_slice())
+            .and_then(|info| info.encrypt_with_params(params, "hunter2"))
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let pem = encrypted
+            .to_pem("ENCRYPTED PRIVATE KEY", pkcs8::LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        std::fs::write(&key_path, pem.as_bytes())?;
+
+        assert!(common::read_key(&key_path).is_err());
+        let passphrase = common::Passphrase::callback(|| Ok("hunter2".to_string()));
+        assert_eq!(
+            common::read_key_with_passphrase(&key_path, Some(&passphrase))?,
+            key
+        );
+        common::load_certified_key(&cert_path, &key_path, Some(&passphrase))?;
+
+        let config = server::ServerTlsConfig::new(&cert_path, &key_path);
+        assert!(config.rustls_config().is_err());
+        config.with_passphrase(passphrase).rustls_config()?;
+        Ok(())
+    }
 }
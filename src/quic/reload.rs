@@ -0,0 +1,263 @@
+use anyhow::Result;
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::common::{self, Passphrase};
+use crate::tls::ocsp::{self, OcspResponse, OcspStatus};
+
+/// outcome of the most recent reload attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadStatus {
+    /// `generation` counts successful loads, the first load is generation 0
+    Loaded { generation: u64 },
+    /// the new pair was rejected and `generation` is still being served
+    Failed { generation: u64, error: String },
+}
+
+/// certificate resolver that can swap its cert/key pair while the server is running
+///
+/// new handshakes pick up the latest pair, established connections are untouched.
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    passphrase: Option<Passphrase>,
+    ocsp_path: Option<PathBuf>,
+    current: RwLock<Arc<CertifiedKey>>,
+    staple: RwLock<Option<OcspResponse>>,
+    stale: AtomicBool,
+    modified: Mutex<[Option<SystemTime>; 3]>,
+    generation: Mutex<u64>,
+    status: watch::Sender<ReloadStatus>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(cert_path: &Path, key_path: &Path) -> Result<Arc<Self>> {
+        Self::new_with_passphrase(cert_path, key_path, None)
+    }
+
+    /// resolver for an encrypted key, the passphrase is resolved again on every reload
+    pub fn new_with_passphrase(
+        cert_path: &Path,
+        key_path: &Path,
+        passphrase: Option<Passphrase>,
+    ) -> Result<Arc<Self>> {
+        Self::open(cert_path, key_path, passphrase, None)
+    }
+
+    /// resolver that staples the DER OCSP response in `ocsp_path` to every handshake
+    ///
+    /// the response must be for the leaf certificate and is re-read with the cert and key.
+    /// `watch` logs a warning when its `nextUpdate` passes and again once it is replaced.
+    pub fn new_with_ocsp(
+        cert_path: &Path,
+        key_path: &Path,
+        passphrase: Option<Passphrase>,
+        ocsp_path: &Path,
+    ) -> Result<Arc<Self>> {
+        Self::open(
+            cert_path,
+            key_path,
+            passphrase,
+            Some(ocsp_path.to_path_buf()),
+        )
+    }
+
+    fn open(
+        cert_path: &Path,
+        key_path: &Path,
+        passphrase: Option<Passphrase>,
+        ocsp_path: Option<PathBuf>,
+    ) -> Result<Arc<Self>> {
+        let modified = [
+            modified_time(cert_path),
+            modified_time(key_path),
+            ocsp_path.as_deref().and_then(modified_time),
+        ];
+        let (certified, staple) = load(
+            cert_path,
+            key_path,
+            passphrase.as_ref(),
+            ocsp_path.as_deref(),
+        )?;
+        let (status, _) = watch::channel(ReloadStatus::Loaded { generation: 0 });
+        Ok(Arc::new(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            passphrase,
+            ocsp_path,
+            current: RwLock::new(Arc::new(certified)),
+            staple: RwLock::new(staple),
+            stale: AtomicBool::new(false),
+            modified: Mutex::new(modified),
+            generation: Mutex::new(0),
+            status,
+        }))
+    }
+
+    /// the OCSP response currently stapled to handshakes
+    pub fn ocsp_response(&self) -> Option<OcspResponse> {
+        self.staple.read().expect("ocsp lock poisoned").clone()
+    }
+
+    /// the pair currently handed out to new handshakes
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().expect("cert lock poisoned").clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ReloadStatus> {
+        self.status.subscribe()
+    }
+
+    /// re-read the cert and key, keeping the current pair if the new one fails validation
+    pub fn reload(&self) -> Result<()> {
+        let mut generation = self.generation.lock().expect("generation lock poisoned");
+        let loaded = load(
+            &self.cert_path,
+            &self.key_path,
+            self.passphrase.as_ref(),
+            self.ocsp_path.as_deref(),
+        );
+        match loaded {
+            Ok((certified, staple)) => {
+                *self.current.write().expect("cert lock poisoned") = Arc::new(certified);
+                *self.staple.write().expect("ocsp lock poisoned") = staple;
+                *generation += 1;
+                tracing::info!(
+                    "reloaded certificate {} (generation {})",
+                    self.cert_path.display(),
+                    *generation
+                );
+                self.status.send_replace(ReloadStatus::Loaded {
+                    generation: *generation,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!(
+                    "failed to reload certificate {}, keeping generation {}: {:?}",
+                    self.cert_path.display(),
+                    *generation,
+                    e
+                );
+                self.status.send_replace(ReloadStatus::Failed {
+                    generation: *generation,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// true if any file's modification time changed since the last check
+    fn changed_on_disk(&self) -> bool {
+        let current = [
+            modified_time(&self.cert_path),
+            modified_time(&self.key_path),
+            self.ocsp_path.as_deref().and_then(modified_time),
+        ];
+        let mut modified = self.modified.lock().expect("modified lock poisoned");
+        if *modified == current {
+            return false;
+        }
+        *modified = current;
+        true
+    }
+
+    fn warn_if_stale(&self) {
+        let (Some(path), Some(response)) = (self.ocsp_path.as_ref(), self.ocsp_response()) else {
+            return;
+        };
+        let stale = response.is_stale();
+        if self.stale.swap(stale, Ordering::Relaxed) == stale {
+            return;
+        }
+        if stale {
+            tracing::warn!(
+                "stapled OCSP response {} is past its nextUpdate, fetch a new one",
+                path.display()
+            );
+        } else {
+            tracing::info!("stapled OCSP response {} is current again", path.display());
+        }
+    }
+
+    /// server config with safe defaults for TLS over TCP, handing out the current pair
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+
+    pub fn tls_acceptor(self: &Arc<Self>) -> tokio_rustls::TlsAcceptor {
+        tokio_rustls::TlsAcceptor::from(Arc::new(self.server_config()))
+    }
+
+    /// poll the cert, key and OCSP files every `interval`, reloading when they change
+    ///
+    /// the task exits once the resolver is dropped.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let resolver = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                let Some(resolver) = resolver.upgrade() else {
+                    break;
+                };
+                if resolver.changed_on_disk() {
+                    // failures are logged and published by reload
+                    let _ = resolver.reload();
+                }
+                resolver.warn_if_stale();
+            }
+        })
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+fn load(
+    cert_path: &Path,
+    key_path: &Path,
+    passphrase: Option<&Passphrase>,
+    ocsp_path: Option<&Path>,
+) -> Result<(CertifiedKey, Option<OcspResponse>)> {
+    let mut certified = common::load_certified_key(cert_path, key_path, passphrase)?;
+    let staple = match ocsp_path {
+        Some(path) => {
+            let response = ocsp::read_ocsp_response(path, &certified.cert)?;
+            if response.status == OcspStatus::Revoked {
+                tracing::error!(
+                    "OCSP response {} reports the certificate as revoked",
+                    path.display()
+                );
+            }
+            certified.ocsp = Some(response.der.clone());
+            Some(response)
+        }
+        None => None,
+    };
+    Ok((certified, staple))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
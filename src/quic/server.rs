@@ -1,24 +1,358 @@
 use anyhow::Result;
 use s2n_quic::{stream::BidirectionalStream, Connection};
 
-use std::{net::SocketAddr, path::Path, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use futures::Future;
+use tokio::task::JoinHandle;
 
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, ClientCertVerifier, ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+use super::{
+    common::{self, Passphrase, TlsProvider},
+    reload::ReloadingCertResolver,
+    sni::SniCertResolver,
+};
+use crate::tls::{
+    keylog::{self, KeyLogging},
+    signer::{KeySource, Signer},
+    trust::TrustStore,
+};
+
+/// keys are read like `read_key`, encrypted keys fail with a format error unless given a
+/// passphrase through `get_server_with_passphrase` or `ServerTlsConfig::with_passphrase`
 pub fn get_server(cert_path: &Path, key_path: &Path, addr: SocketAddr) -> Result<s2n_quic::Server> {
+    ServerTlsConfig::new(cert_path, key_path).start(addr)
+}
+
+/// like `get_server` with an explicit choice of TLS provider
+pub fn get_server_with_provider(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: TlsProvider,
+    addr: SocketAddr,
+) -> Result<s2n_quic::Server> {
+    ServerTlsConfig::new(cert_path, key_path)
+        .with_provider(provider)
+        .start(addr)
+}
+
+/// tls settings for QUIC servers, with the same options for either provider
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    provider: TlsProvider,
+    cert_path: PathBuf,
+    key: KeySource,
+    client_ca_path: Option<PathBuf>,
+    alpn_protocols: Vec<Vec<u8>>,
+    key_log: Option<KeyLogging>,
+    reload_interval: Option<Duration>,
+}
+
+impl ServerTlsConfig {
+    pub fn new(cert_path: &Path, key_path: &Path) -> Self {
+        Self::with_key(cert_path, KeySource::file(key_path))
+    }
+
+    /// server whose handshake signatures are made by `signer`, rustls provider only
+    pub fn from_signer(cert_path: &Path, signer: Arc<dyn Signer>) -> Self {
+        Self::with_key(cert_path, KeySource::Signer(signer))
+    }
+
+    fn with_key(cert_path: &Path, key: KeySource) -> Self {
+        Self {
+            provider: TlsProvider::default(),
+            cert_path: cert_path.to_path_buf(),
+            key,
+            client_ca_path: None,
+            alpn_protocols: vec![common::QUIC_ALPN.to_vec()],
+            key_log: None,
+            reload_interval: None,
+        }
+    }
+
+    /// passphrase for an encrypted key, ignored for servers backed by a signer
+    ///
+    /// s2n-tls cannot read encrypted keys, `start` fails with that provider.
+    pub fn with_passphrase(mut self, passphrase: Passphrase) -> Self {
+        if let KeySource::File { passphrase: p, .. } = &mut self.key {
+            *p = Some(passphrase);
+        }
+        self
+    }
+
+    /// re-read the cert and key every `interval` when they change, see
+    /// `ReloadingCertResolver`
+    ///
+    /// rustls provider only. the files are watched by the server `start` returns and the
+    /// watcher stops once that server is dropped, `rustls_config` alone never reloads.
+    pub fn with_reload(mut self, interval: Duration) -> Self {
+        self.reload_interval = Some(interval);
+        self
+    }
+
+    pub fn with_provider(mut self, provider: TlsProvider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// require clients to present a certificate issued by a CA in `path`
+    pub fn with_client_ca(mut self, path: &Path) -> Self {
+        self.client_ca_path = Some(path.to_path_buf());
+        self
+    }
+
+    /// protocols accepted in ALPN, `h3` by default
+    pub fn with_alpn_protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.alpn_protocols = protocols.into_iter().map(|p| p.as_ref().to_vec()).collect();
+        self
+    }
+
+    /// write session secrets to `key_log` so captures can be decrypted, never in production
+    ///
+    /// s2n-tls only supports `KeyLogging::Env`.
+    pub fn with_key_log(mut self, key_log: KeyLogging) -> Self {
+        self.key_log = Some(key_log);
+        self
+    }
+
+    pub fn rustls_config(&self) -> Result<ServerConfig> {
+        Ok(self.rustls_parts()?.0)
+    }
+
+    /// the rustls config and, with `with_reload`, the resolver to watch
+    fn rustls_parts(&self) -> Result<(ServerConfig, Option<Arc<ReloadingCertResolver>>)> {
+        let mut reloading = None;
+        let resolver: Arc<dyn ResolvesServerCert> = match (&self.key, self.reload_interval) {
+            (KeySource::File { path, passphrase }, Some(_)) => {
+                let resolver = ReloadingCertResolver::new_with_passphrase(
+                    &self.cert_path,
+                    path,
+                    passphrase.clone(),
+                )?;
+                reloading = Some(resolver.clone());
+                resolver
+            }
+            (KeySource::Signer(_), Some(_)) => {
+                return Err(anyhow::anyhow!("signers cannot be reloaded from disk"))
+            }
+            (key, None) => Arc::new(SingleCert(Arc::new(key.certified_key(&self.cert_path)?))),
+        };
+        let builder = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])?;
+        let builder = match self.client_ca_path.as_ref() {
+            Some(path) => {
+                let roots = TrustStore::from_ca_file(path).root_store()?;
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(resolver);
+        config.alpn_protocols = self.alpn_protocols.clone();
+        config.max_early_data_size = u32::MAX;
+        if let Some(key_log) = self.key_log.as_ref() {
+            config.key_log = key_log.open()?;
+        }
+        Ok((config, reloading))
+    }
+
+    pub fn start(&self, addr: SocketAddr) -> Result<s2n_quic::Server> {
+        let builder = s2n_quic::Server::builder().with_io(addr)?;
+        match self.provider {
+            TlsProvider::Rustls => {
+                let (config, reloading) = self.rustls_parts()?;
+                let server = builder
+                    .with_tls(s2n_quic::provider::tls::rustls::Server::from(config))?
+                    .start()?;
+                if let (Some(resolver), Some(interval)) = (reloading, self.reload_interval) {
+                    // holds the resolver weakly, so it exits once the server is dropped
+                    resolver.watch(interval);
+                }
+                Ok(server)
+            }
+            TlsProvider::S2nTls => {
+                let key_path = match &self.key {
+                    KeySource::File {
+                        path,
+                        passphrase: None,
+                    } => path,
+                    KeySource::File { .. } => {
+                        return Err(anyhow::anyhow!(
+                            "s2n-tls cannot read encrypted keys, use the rustls provider"
+                        ))
+                    }
+                    KeySource::Signer(_) => {
+                        return Err(anyhow::anyhow!("signers need the rustls provider"))
+                    }
+                };
+                if self.reload_interval.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "certificate reloading needs the rustls provider"
+                    ));
+                }
+                let mut tls = s2n_quic::provider::tls::s2n_tls::Server::builder()
+                    .with_certificate(self.cert_path.as_path(), key_path.as_path())?
+                    .with_application_protocols(self.alpn_protocols.iter())?;
+                if let Some(path) = self.client_ca_path.as_ref() {
+                    tls = tls
+                        .with_client_authentication()?
+                        .with_trusted_certificate(path.as_path())?;
+                }
+                if let Some(key_log) = self.key_log.as_ref() {
+                    keylog::warn_enabled(&key_log.s2n_path()?);
+                    tls = tls.with_key_logging()?;
+                }
+                Ok(builder.with_tls(tls.build()?)?.start()?)
+            }
+        }
+    }
+}
+
+struct SingleCert(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for SingleCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+pub async fn run_server<F, Fut>(
+    cert_path: &Path,
+    key_path: &Path,
+    addr: SocketAddr,
+    handler: F,
+) -> Result<()>
+where
+    F: Fn(Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let server = get_server(cert_path, key_path, addr)?;
+    serve(server, handler).await
+}
+
+/// like `get_server` for private keys that are encrypted with a passphrase
+pub fn get_server_with_passphrase(
+    cert_path: &Path,
+    key_path: &Path,
+    passphrase: &Passphrase,
+    addr: SocketAddr,
+) -> Result<s2n_quic::Server> {
+    ServerTlsConfig::new(cert_path, key_path)
+        .with_passphrase(passphrase.clone())
+        .start(addr)
+}
+
+/// server that hands new handshakes the latest pair from `resolver`
+pub fn get_reloadable_server(
+    resolver: Arc<ReloadingCertResolver>,
+    addr: SocketAddr,
+) -> Result<s2n_quic::Server> {
+    server_with_resolver(resolver, addr)
+}
+
+/// server choosing its certificate by the SNI each client sends
+pub fn get_sni_server(resolver: SniCertResolver, addr: SocketAddr) -> Result<s2n_quic::Server> {
+    server_with_resolver(Arc::new(resolver), addr)
+}
+
+fn server_with_resolver(
+    resolver: Arc<dyn ResolvesServerCert>,
+    addr: SocketAddr,
+) -> Result<s2n_quic::Server> {
+    let tls = s2n_quic::provider::tls::rustls::Server::builder()
+        .with_cert_resolver(resolver)?
+        .build()?;
     let server = s2n_quic::Server::builder()
-        .with_tls((cert_path, key_path))?
+        .with_tls(tls)?
         .with_io(addr)?
         .start()?;
     Ok(server)
 }
 
-pub async fn run_server<F, Fut>(
+/// server stapling the DER OCSP response in `ocsp_path`, refreshed every `refresh_interval`
+///
+/// the cert, key and response are re-read when they change on disk. must be called
+/// from within a tokio runtime, the returned refresh task stops when the server is dropped
+/// or the handle is aborted.
+pub fn get_server_with_ocsp(
+    cert_path: &Path,
+    key_path: &Path,
+    ocsp_path: &Path,
+    refresh_interval: Duration,
+    addr: SocketAddr,
+) -> Result<(s2n_quic::Server, JoinHandle<()>)> {
+    let resolver = ReloadingCertResolver::new_with_ocsp(cert_path, key_path, None, ocsp_path)?;
+    let watcher = resolver.watch(refresh_interval);
+    match get_reloadable_server(resolver, addr) {
+        Ok(server) => Ok((server, watcher)),
+        Err(e) => {
+            watcher.abort();
+            Err(e)
+        }
+    }
+}
+
+/// mutual TLS server, clients must present a certificate accepted by `client_verifier`
+///
+/// pair a `CrlClientVerifier` with `RevocationList::watch` to reject revoked clients.
+pub fn get_server_with_client_auth(
+    resolver: Arc<dyn ResolvesServerCert>,
+    client_verifier: Arc<dyn ClientCertVerifier>,
+    addr: SocketAddr,
+) -> Result<s2n_quic::Server> {
+    let mut config = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_client_cert_verifier(client_verifier)
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![common::QUIC_ALPN.to_vec()];
+    config.max_early_data_size = u32::MAX;
+    let tls = s2n_quic::provider::tls::rustls::Server::from(config);
+    let server = s2n_quic::Server::builder()
+        .with_tls(tls)?
+        .with_io(addr)?
+        .start()?;
+    Ok(server)
+}
+
+/// like `run_server` but reloads the cert and key when they change on disk
+pub async fn run_reloadable_server<F, Fut>(
     cert_path: &Path,
     key_path: &Path,
     addr: SocketAddr,
+    reload_interval: Duration,
     handler: F,
 ) -> Result<()>
+where
+    F: Fn(Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let resolver = ReloadingCertResolver::new(cert_path, key_path)?;
+    let watcher = resolver.watch(reload_interval);
+    let server = get_reloadable_server(resolver, addr)?;
+    let result = serve(server, handler).await;
+    watcher.abort();
+    result
+}
+
+/// spawn `handler` for every connection accepted by `server`
+pub async fn serve<F, Fut>(server: s2n_quic::Server, handler: F) -> Result<()>
 where
     F: Fn(Connection) -> Fut + Send + Sync + 'static,
     Fut: Future<Output = Result<()>> + Send + 'static,
@@ -26,7 +360,7 @@ where
     let handler = Arc::new(handler);
     tokio::pin!(handler);
 
-    let mut server = get_server(cert_path, key_path, addr)?;
+    let mut server = server;
     while let Some(connection) = server.accept().await {
         let handler = handler.clone();
         tokio::spawn(async move {
@@ -45,6 +379,16 @@ pub async fn run_bidirectional_server<F, Fut>(
     addr: SocketAddr,
     handler: F,
 ) -> Result<()>
+where
+    F: Fn(BidirectionalStream) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let server = get_server(cert_path, key_path, addr)?;
+    serve_bidirectional(server, handler).await
+}
+
+/// spawn `handler` for every bidirectional stream of every connection accepted by `server`
+pub async fn serve_bidirectional<F, Fut>(server: s2n_quic::Server, handler: F) -> Result<()>
 where
     F: Fn(BidirectionalStream) -> Fut + Send + Sync + 'static,
     Fut: Future<Output = Result<()>> + Send + 'static,
@@ -52,7 +396,7 @@ where
     let handler = Arc::new(handler);
     tokio::pin!(handler);
 
-    let mut server = get_server(cert_path, key_path, addr)?;
+    let mut server = server;
     while let Some(mut connection) = server.accept().await {
         let handler = handler.clone();
         // spawn a new task for the connection
@@ -60,7 +404,7 @@ where
             while let Ok(Some(stream)) = connection.accept_bidirectional_stream().await {
                 let handler = handler(stream);
                 // spawn a new task for the stream
-                let _ = tokio::spawn(async move {
+                tokio::spawn(async move {
                     if let Err(e) = handler.await {
                         let msg = format!("stream task failed {:?}", e);
                         tracing::error!("{}", msg);
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use futures::{future::BoxFuture, Future, FutureExt};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use s2n_quic::Connection;
+use std::{collections::HashMap, net::SocketAddr, path::Path, sync::Arc};
+
+use super::{common, server};
+
+/// values keyed by server name, `*.example.com` matches exactly one extra label
+#[derive(Clone)]
+pub(crate) struct HostMap<T> {
+    exact: HashMap<String, T>,
+    wildcard: HashMap<String, T>,
+    pub(crate) default: Option<T>,
+}
+
+impl<T> Default for HostMap<T> {
+    fn default() -> Self {
+        Self {
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+            default: None,
+        }
+    }
+}
+
+impl<T> HostMap<T> {
+    pub(crate) fn insert(&mut self, name: &str, value: T) {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        match name.strip_prefix("*.") {
+            Some(parent) => self.wildcard.insert(parent.to_string(), value),
+            None => self.exact.insert(name, value),
+        };
+    }
+
+    /// exact match first, then wildcard, then the default
+    pub(crate) fn get(&self, name: Option<&str>) -> Option<&T> {
+        let Some(name) = name.map(|n| n.trim_end_matches('.').to_ascii_lowercase()) else {
+            return self.default.as_ref();
+        };
+        self.exact
+            .get(&name)
+            .or_else(|| {
+                name.split_once('.')
+                    .and_then(|(_, parent)| self.wildcard.get(parent))
+            })
+            .or(self.default.as_ref())
+    }
+}
+
+#[derive(Clone)]
+enum CertSource {
+    Static(Arc<CertifiedKey>),
+    Resolver(Arc<dyn ResolvesServerCert>),
+}
+
+/// certificate resolver that picks a cert/key pair by the SNI the client sent
+///
+/// clients that send no SNI, or a name without an entry, get the default pair if one
+/// is set and fail the handshake otherwise.
+#[derive(Clone, Default)]
+pub struct SniCertResolver {
+    hosts: HostMap<CertSource>,
+}
+
+impl SniCertResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// serve `certified` for `name`, which may be a wildcard such as `*.example.com`
+    pub fn with_certified_key(mut self, name: &str, certified: CertifiedKey) -> Self {
+        self.hosts
+            .insert(name, CertSource::Static(Arc::new(certified)));
+        self
+    }
+
+    pub fn with_cert(self, name: &str, cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let certified = common::load_certified_key(cert_path, key_path, None)?;
+        Ok(self.with_certified_key(name, certified))
+    }
+
+    /// delegate `name` to another resolver, e.g. a `ReloadingCertResolver`
+    pub fn with_resolver(mut self, name: &str, resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        self.hosts.insert(name, CertSource::Resolver(resolver));
+        self
+    }
+
+    pub fn with_default(mut self, certified: CertifiedKey) -> Self {
+        self.hosts.default = Some(CertSource::Static(Arc::new(certified)));
+        self
+    }
+
+    pub fn with_default_resolver(mut self, resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        self.hosts.default = Some(CertSource::Resolver(resolver));
+        self
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name();
+        match self.hosts.get(name) {
+            Some(CertSource::Static(certified)) => Some(certified.clone()),
+            Some(CertSource::Resolver(resolver)) => resolver.resolve(client_hello),
+            None => {
+                tracing::warn!("no certificate for server name {:?}", name);
+                None
+            }
+        }
+    }
+}
+
+type Handler = Arc<dyn Fn(Connection) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// dispatches each connection to a handler chosen by its SNI
+///
+/// names are matched like `SniCertResolver`, connections without a matching route are
+/// closed.
+#[derive(Clone, Default)]
+pub struct SniRouter {
+    routes: HostMap<Handler>,
+}
+
+impl SniRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.routes.insert(
+            name,
+            Arc::new(move |connection| handler(connection).boxed()),
+        );
+        self
+    }
+
+    pub fn default_route<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.routes.default = Some(Arc::new(move |connection| handler(connection).boxed()));
+        self
+    }
+
+    pub async fn dispatch(&self, connection: Connection) -> Result<()> {
+        let name = connection.server_name()?;
+        match self.routes.get(name.as_deref()) {
+            Some(handler) => handler(connection).await,
+            None => {
+                tracing::warn!("no handler for server name {:?}, closing", name.as_deref());
+                connection.close(s2n_quic::application::Error::UNKNOWN);
+                Ok(())
+            }
+        }
+    }
+
+    /// accept connections on `server` until it shuts down
+    pub async fn serve(self, server: s2n_quic::Server) -> Result<()> {
+        let router = Arc::new(self);
+        server::serve(server, move |connection| {
+            let router = router.clone();
+            async move { router.dispatch(connection).await }
+        })
+        .await
+    }
+}
+
+/// serve several hostnames from one address, routing each connection by its SNI
+pub async fn run_sni_server(
+    resolver: SniCertResolver,
+    router: SniRouter,
+    addr: SocketAddr,
+) -> Result<()> {
+    let server = server::get_sni_server(resolver, addr)?;
+    router.serve(server).await
+}
@@ -0,0 +1,323 @@
+use anyhow::Result;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    server::{ClientCertVerified, ClientCertVerifier},
+    Certificate, CertificateError, ClientConfig, DistinguishedName, ServerName,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::task::JoinHandle;
+
+use super::trust::TrustStore;
+
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// read every CRL in a PEM (`X509 CRL` blocks) or DER file
+pub fn read_crls(path: &Path) -> Result<Vec<webpki::OwnedCertRevocationList>> {
+    let raw = std::fs::read(path)?;
+    let ders = if raw.starts_with(b"-----") || raw.windows(11).any(|w| w == b"-----BEGIN ") {
+        rustls_pemfile::read_all(&mut raw.as_slice())?
+            .into_iter()
+            .filter_map(|item| match item {
+                rustls_pemfile::Item::Crl(der) => Some(der),
+                _ => None,
+            })
+            .collect()
+    } else {
+        vec![raw]
+    };
+    if ders.is_empty() {
+        return Err(anyhow::anyhow!("no CRL found in {}", path.display()));
+    }
+    ders.iter()
+        .map(|der| {
+            webpki::BorrowedCertRevocationList::from_der(der)
+                .and_then(|crl| crl.to_owned())
+                .map_err(|e| anyhow::anyhow!("invalid CRL in {}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+/// certificate revocation lists shared by verifiers and refreshed from disk
+pub struct RevocationList {
+    paths: Vec<PathBuf>,
+    current: RwLock<Arc<Vec<webpki::OwnedCertRevocationList>>>,
+}
+
+impl RevocationList {
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Arc<Self>> {
+        let paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        let current = Self::load(&paths)?;
+        Ok(Arc::new(Self {
+            paths,
+            current: RwLock::new(Arc::new(current)),
+        }))
+    }
+
+    fn load(paths: &[PathBuf]) -> Result<Vec<webpki::OwnedCertRevocationList>> {
+        let mut crls = Vec::new();
+        for path in paths.iter() {
+            crls.extend(read_crls(path)?);
+        }
+        Ok(crls)
+    }
+
+    pub(crate) fn current(&self) -> Arc<Vec<webpki::OwnedCertRevocationList>> {
+        self.current.read().expect("crl lock poisoned").clone()
+    }
+
+    /// re-read every file, keeping the current lists if any of them fails to parse
+    pub fn reload(&self) -> Result<()> {
+        match Self::load(&self.paths) {
+            Ok(crls) => {
+                tracing::info!("reloaded {} revocation lists", crls.len());
+                *self.current.write().expect("crl lock poisoned") = Arc::new(crls);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("failed to reload revocation lists, keeping current {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// reload every `interval`, the task exits once the list is dropped
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let crls = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            // the first tick completes immediately and the lists were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(crls) = crls.upgrade() else {
+                    break;
+                };
+                // failures are logged by reload
+                let _ = crls.reload();
+            }
+        })
+    }
+}
+
+/// trust anchors in the form webpki takes them, loaded once per verifier
+struct Anchors {
+    webpki_roots: bool,
+    certificates: Vec<Vec<u8>>,
+}
+
+impl Anchors {
+    fn new(trust: &TrustStore) -> Result<Self> {
+        let anchors = Self {
+            webpki_roots: trust.uses_webpki_roots(),
+            certificates: trust.certificate_ders()?,
+        };
+        if anchors.trust_anchors().is_empty() {
+            return Err(anyhow::anyhow!("trust store has no roots"));
+        }
+        Ok(anchors)
+    }
+
+    fn trust_anchors(&self) -> Vec<webpki::TrustAnchor<'_>> {
+        let bundled = webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .filter(|_| self.webpki_roots)
+            .map(|ta| webpki::TrustAnchor {
+                subject: ta.subject,
+                spki: ta.spki,
+                name_constraints: ta.name_constraints,
+            });
+        let certificates = self
+            .certificates
+            .iter()
+            .filter_map(|der| webpki::TrustAnchor::try_from_cert_der(der).ok());
+        bundled.chain(certificates).collect()
+    }
+
+    /// validate the chain and check every certificate in it against `crls`
+    fn verify(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+        usage: webpki::KeyUsage,
+        crls: &RevocationList,
+    ) -> Result<(), rustls::Error> {
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_slice())
+            .map_err(|e| certificate_error(e, end_entity))?;
+        let intermediates: Vec<&[u8]> = intermediates.iter().map(|c| c.0.as_slice()).collect();
+        let now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        let current = crls.current();
+        let crls: Vec<&dyn webpki::CertRevocationList> = current
+            .iter()
+            .map(|crl| crl as &dyn webpki::CertRevocationList)
+            .collect();
+        cert.verify_for_usage(
+            SIGNATURE_ALGORITHMS,
+            &self.trust_anchors(),
+            &intermediates,
+            now,
+            usage,
+            &crls,
+        )
+        .map_err(|e| certificate_error(e, end_entity))
+    }
+}
+
+fn certificate_error(e: webpki::Error, cert: &Certificate) -> rustls::Error {
+    let error = match e {
+        webpki::Error::CertRevoked => {
+            let subject = x509_parser::parse_x509_certificate(&cert.0)
+                .map(|(_, c)| c.subject().to_string())
+                .unwrap_or_default();
+            tracing::warn!("rejected revoked certificate {:?}", subject);
+            CertificateError::Revoked
+        }
+        webpki::Error::BadDer | webpki::Error::BadDerTime => CertificateError::BadEncoding,
+        webpki::Error::CertExpired | webpki::Error::InvalidCertValidity => {
+            CertificateError::Expired
+        }
+        webpki::Error::CertNotValidYet => CertificateError::NotValidYet,
+        webpki::Error::CertNotValidForName => CertificateError::NotValidForName,
+        webpki::Error::UnknownIssuer => CertificateError::UnknownIssuer,
+        webpki::Error::InvalidSignatureForPublicKey
+        | webpki::Error::UnsupportedSignatureAlgorithm
+        | webpki::Error::UnsupportedSignatureAlgorithmForPublicKey => {
+            CertificateError::BadSignature
+        }
+        e => CertificateError::Other(Arc::new(e)),
+    };
+    rustls::Error::InvalidCertificate(error)
+}
+
+/// server verifier that rejects certificates listed in a `RevocationList`
+pub struct CrlServerVerifier {
+    anchors: Anchors,
+    crls: Arc<RevocationList>,
+}
+
+impl CrlServerVerifier {
+    pub fn new(trust: &TrustStore, crls: Arc<RevocationList>) -> Result<Self> {
+        Ok(Self {
+            anchors: Anchors::new(trust)?,
+            crls,
+        })
+    }
+
+    /// client config with safe defaults for TLS over TCP
+    pub fn client_config(self) -> ClientConfig {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(self))
+            .with_no_client_auth()
+    }
+
+    pub fn tls_connector(self) -> tokio_rustls::TlsConnector {
+        tokio_rustls::TlsConnector::from(Arc::new(self.client_config()))
+    }
+}
+
+impl ServerCertVerifier for CrlServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.anchors.verify(
+            end_entity,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            &self.crls,
+        )?;
+
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_slice())
+            .map_err(|e| certificate_error(e, end_entity))?;
+        let verified = match server_name {
+            ServerName::DnsName(name) => webpki::SubjectNameRef::try_from_ascii_str(name.as_ref())
+                .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))
+                .and_then(|name| {
+                    cert.verify_is_valid_for_subject_name(name)
+                        .map_err(|e| certificate_error(e, end_entity))
+                }),
+            ServerName::IpAddress(ip) => {
+                let ip = webpki::IpAddr::from(*ip);
+                cert.verify_is_valid_for_subject_name(webpki::SubjectNameRef::IpAddress(
+                    webpki::IpAddrRef::from(&ip),
+                ))
+                .map_err(|e| certificate_error(e, end_entity))
+            }
+            _ => Err(rustls::Error::UnsupportedNameType),
+        };
+        verified.map(|_| ServerCertVerified::assertion())
+    }
+}
+
+/// client certificate verifier for mTLS servers that rejects revoked client certificates
+///
+/// every client must present a certificate chaining to `trust`.
+pub struct CrlClientVerifier {
+    anchors: Anchors,
+    subjects: Vec<DistinguishedName>,
+    crls: Arc<RevocationList>,
+}
+
+impl CrlClientVerifier {
+    pub fn new(trust: &TrustStore, crls: Arc<RevocationList>) -> Result<Self> {
+        let subjects = trust
+            .root_store()?
+            .roots
+            .iter()
+            .map(|root| root.subject().clone())
+            .collect();
+        Ok(Self {
+            anchors: Anchors::new(trust)?,
+            subjects,
+            crls,
+        })
+    }
+}
+
+impl ClientCertVerifier for CrlClientVerifier {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &self.subjects
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.anchors
+            .verify(
+                end_entity,
+                intermediates,
+                now,
+                webpki::KeyUsage::client_auth(),
+                &self.crls,
+            )
+            .map(|_| ClientCertVerified::assertion())
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use rustls::KeyLog;
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::to_hex;
+
+/// where to write TLS secrets for decrypting captured traffic
+///
+/// anyone holding the file can decrypt every logged session, only enable it while
+/// debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyLogging {
+    /// the path in the `SSLKEYLOGFILE` environment variable
+    Env,
+    Path(PathBuf),
+}
+
+impl KeyLogging {
+    pub(crate) fn path(&self) -> Result<PathBuf> {
+        match self {
+            KeyLogging::Env => std::env::var_os("SSLKEYLOGFILE")
+                .map(PathBuf::from)
+                .ok_or_else(|| anyhow::anyhow!("SSLKEYLOGFILE is not set")),
+            KeyLogging::Path(path) => Ok(path.clone()),
+        }
+    }
+
+    /// s2n-tls reads `SSLKEYLOGFILE` itself and cannot be pointed elsewhere
+    pub(crate) fn s2n_path(&self) -> Result<PathBuf> {
+        match self {
+            KeyLogging::Env => self.path(),
+            KeyLogging::Path(_) => Err(anyhow::anyhow!(
+                "s2n-tls only logs keys to SSLKEYLOGFILE, use the rustls provider"
+            )),
+        }
+    }
+
+    pub fn open(&self) -> Result<Arc<KeyLogFile>> {
+        KeyLogFile::open(&self.path()?)
+    }
+}
+
+/// appends NSS key log lines, the format Wireshark reads
+pub struct KeyLogFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl KeyLogFile {
+    pub fn open(path: &Path) -> Result<Arc<Self>> {
+        let mut options = std::fs::OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path)?;
+        warn_enabled(path);
+        Ok(Arc::new(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        }))
+    }
+}
+
+pub(crate) fn warn_enabled(path: &Path) {
+    tracing::warn!(
+        "TLS key logging is enabled, session secrets are written to {}; \
+         traffic can be decrypted by anyone with this file",
+        path.display()
+    );
+}
+
+impl fmt::Debug for KeyLogFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLogFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!("{} {} {}\n", label, to_hex(client_random), to_hex(secret));
+        let mut file = self.file.lock().expect("key log lock poisoned");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::error!("failed to write key log {}: {:?}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, ClientConfig, ServerName,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use super::pinning::Pin;
+use crate::{error::NetworkError, quic::common};
+
+/// result of checking a server against the known hosts file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostStatus {
+    /// the certificate matches the recorded fingerprint
+    Known,
+    /// first connection to this name, the fingerprint has been recorded
+    Added,
+}
+
+/// ssh style trust-on-first-use store of certificate fingerprints
+///
+/// the file holds one `<server name> sha256:<hex>` line per host, blank lines and
+/// lines starting with `#` are ignored. remove a host's line after reflashing it.
+#[derive(Debug)]
+pub struct KnownHosts {
+    path: PathBuf,
+    hosts: Mutex<BTreeMap<String, Pin>>,
+    // (expected, presented) for hosts the verifier rejected, a failed handshake only
+    // carries the TLS alert
+    rejected: Mutex<BTreeMap<String, (String, String)>>,
+}
+
+impl KnownHosts {
+    /// load `path`, a missing file is treated as empty and created on the first connection
+    pub fn open(path: &Path) -> Result<Arc<Self>> {
+        let mut hosts = BTreeMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                for (number, line) in contents.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let (host, pin) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                        anyhow::anyhow!("{}:{}: missing fingerprint", path.display(), number + 1)
+                    })?;
+                    let pin = pin
+                        .trim()
+                        .parse()
+                        .map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+                    hosts.insert(host.to_string(), pin);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Arc::new(Self {
+            path: path.to_path_buf(),
+            hosts: Mutex::new(hosts),
+            rejected: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// the fingerprint recorded for `host`
+    pub fn get(&self, host: &str) -> Option<Pin> {
+        self.hosts
+            .lock()
+            .expect("known hosts lock poisoned")
+            .get(host)
+            .copied()
+    }
+
+    /// compare `cert` with the fingerprint recorded for `host`, recording it if there is none
+    pub fn check(&self, host: &str, cert: &Certificate) -> Result<HostStatus, NetworkError> {
+        let presented = Pin::leaf_of(cert);
+        let mut hosts = self.hosts.lock().expect("known hosts lock poisoned");
+        match hosts.get(host) {
+            Some(expected) if *expected == presented => Ok(HostStatus::Known),
+            Some(expected) => {
+                tracing::error!(
+                    "certificate for {} changed, expected {} but got {}",
+                    host,
+                    expected,
+                    presented
+                );
+                Err(NetworkError::HostCertificateChanged {
+                    host: host.to_string(),
+                    expected: expected.to_string(),
+                    presented: presented.to_string(),
+                })
+            }
+            None => {
+                let mut updated = hosts.clone();
+                updated.insert(host.to_string(), presented);
+                self.persist(&updated)?;
+                *hosts = updated;
+                tracing::info!("recorded certificate {} for {}", presented, host);
+                Ok(HostStatus::Added)
+            }
+        }
+    }
+
+    /// forget `host` so the next connection records its new certificate
+    pub fn remove(&self, host: &str) -> Result<Option<Pin>> {
+        let mut hosts = self.hosts.lock().expect("known hosts lock poisoned");
+        let mut updated = hosts.clone();
+        let removed = updated.remove(host);
+        if removed.is_some() {
+            self.persist(&updated)?;
+            *hosts = updated;
+        }
+        Ok(removed)
+    }
+
+    /// `error` from a failed handshake with `host`, replaced by
+    /// `NetworkError::HostCertificateChanged` if the verifier rejected its certificate
+    pub fn host_error(&self, host: &str, error: anyhow::Error) -> anyhow::Error {
+        let rejected = self
+            .rejected
+            .lock()
+            .expect("known hosts lock poisoned")
+            .remove(host);
+        match rejected {
+            Some((expected, presented)) => NetworkError::HostCertificateChanged {
+                host: host.to_string(),
+                expected,
+                presented,
+            }
+            .into(),
+            None => error,
+        }
+    }
+
+    fn set_rejected(&self, host: &str, rejection: Option<(String, String)>) {
+        let mut rejected = self.rejected.lock().expect("known hosts lock poisoned");
+        match rejection {
+            Some(rejection) => rejected.insert(host.to_string(), rejection),
+            None => rejected.remove(host),
+        };
+    }
+
+    fn persist(&self, hosts: &BTreeMap<String, Pin>) -> std::io::Result<()> {
+        let contents: String = hosts
+            .iter()
+            .map(|(host, pin)| format!("{host} {pin}\n"))
+            .collect();
+        common::write_atomic(&self.path, contents.as_bytes(), 0o644)
+    }
+
+    /// client config with safe defaults for TLS over TCP
+    pub fn client_config(self: &Arc<Self>) -> ClientConfig {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(KnownHostsVerifier(self.clone())))
+            .with_no_client_auth()
+    }
+
+    pub fn tls_connector(self: &Arc<Self>) -> tokio_rustls::TlsConnector {
+        tokio_rustls::TlsConnector::from(Arc::new(self.client_config()))
+    }
+}
+
+/// server verifier backed by a `KnownHosts` file
+///
+/// the chain is not validated, a server is trusted because its certificate is the one
+/// seen on the first connection.
+pub struct KnownHostsVerifier(pub Arc<KnownHosts>);
+
+impl ServerCertVerifier for KnownHostsVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => return Err(rustls::Error::UnsupportedNameType),
+        };
+        match self.0.check(&host, end_entity) {
+            Ok(_) => {
+                self.0.set_rejected(&host, None);
+                Ok(ServerCertVerified::assertion())
+            }
+            Err(NetworkError::HostCertificateChanged {
+                host,
+                expected,
+                presented,
+            }) => {
+                self.0
+                    .set_rejected(&host, Some((expected.clone(), presented.clone())));
+                let e = NetworkError::HostCertificateChanged {
+                    host,
+                    expected,
+                    presented,
+                };
+                Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                    Arc::new(e),
+                )))
+            }
+            Err(e) => Err(rustls::Error::General(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+pub mod crl;
+pub mod keylog;
+pub mod known_hosts;
+pub mod ocsp;
+pub mod pinning;
+pub mod signer;
+pub mod trust;
+
+use anyhow::Result;
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    out
+}
+
+/// DER encoded SubjectPublicKeyInfo of a certificate
+pub(crate) fn spki(cert: &rustls::Certificate) -> Result<Vec<u8>> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)
+        .map_err(|e| anyhow::anyhow!("failed to parse certificate: {}", e))?;
+    Ok(parsed.public_key().raw.to_vec())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.trim()
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => {
+                Some((char::from(*hi).to_digit(16)? * 16 + char::from(*lo).to_digit(16)?) as u8)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quic::common;
+
+    #[test]
+    fn test_trust_store_combines_sources() -> anyhow::Result<()> {
+        assert!(trust::TrustStore::new().root_store().is_err());
+
+        let dir = tempfile::tempdir()?;
+        let ca_path = dir.path().join("ca.pem");
+        common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .cert_path(&ca_path)
+            .generate()?;
+        let bundled = trust::TrustStore::new().with_webpki_roots().root_store()?;
+        let combined = trust::TrustStore::from_ca_file(&ca_path)
+            .with_webpki_roots()
+            .root_store()?;
+        assert_eq!(combined.len(), bundled.len() + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pins_match_leaf_and_spki() -> anyhow::Result<()> {
+        let cert = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .build()?;
+        let der = rustls::Certificate(cert.serialize_der()?);
+        let other = rustls::Certificate(
+            common::CertificateBuilder::new()
+                .dns_name("localhost")
+                .build()?
+                .serialize_der()?,
+        );
+
+        let spki: pinning::Pin = pinning::Pin::spki_of(&der)?.to_string().parse()?;
+        let leaf: pinning::Pin = pinning::Pin::leaf_of(&der).to_string().parse()?;
+        assert!(spki.matches(&der) && leaf.matches(&der));
+        assert!(!spki.matches(&other) && !leaf.matches(&other));
+        assert!("md5:00".parse::<pinning::Pin>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_hosts_records_first_certificate() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("known_hosts");
+        let first = rustls::Certificate(
+            common::CertificateBuilder::new()
+                .dns_name("device.lab")
+                .build()?
+                .serialize_der()?,
+        );
+        let reflashed = rustls::Certificate(
+            common::CertificateBuilder::new()
+                .dns_name("device.lab")
+                .build()?
+                .serialize_der()?,
+        );
+
+        let known = known_hosts::KnownHosts::open(&path)?;
+        assert_eq!(
+            known.check("device.lab", &first)?,
+            known_hosts::HostStatus::Added
+        );
+        let known = known_hosts::KnownHosts::open(&path)?;
+        assert_eq!(
+            known.check("device.lab", &first)?,
+            known_hosts::HostStatus::Known
+        );
+        assert!(matches!(
+            known.check("device.lab", &reflashed),
+            Err(crate::error::NetworkError::HostCertificateChanged { .. })
+        ));
+        known.remove("device.lab")?;
+        assert_eq!(
+            known.check("device.lab", &reflashed)?,
+            known_hosts::HostStatus::Added
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_known_hosts_reports_changed_certificate() -> anyhow::Result<()> {
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let (reflashed, _) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)?;
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut tls) = acceptor.accept(stream).await {
+                    let _ = tls.write_all(b"ok").await;
+                }
+            }
+        });
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("known_hosts");
+        let known = known_hosts::KnownHosts::open(&path)?;
+        known.check("localhost", &reflashed)?;
+
+        let name = rustls::ServerName::try_from("localhost")?;
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let err = match known.tls_connector().connect(name.clone(), stream).await {
+            Ok(_) => panic!("changed certificate was accepted"),
+            Err(e) => known.host_error("localhost", e.into()),
+        };
+        assert!(matches!(
+            err.downcast_ref::<crate::error::NetworkError>(),
+            Some(crate::error::NetworkError::HostCertificateChanged { host, .. }) if host == "localhost"
+        ));
+
+        known.remove("localhost")?;
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut tls = known.tls_connector().connect(name, stream).await?;
+        let mut reply = Vec::new();
+        tls.read_to_end(&mut reply).await.ok();
+        assert_eq!(reply, b"ok");
+
+        // nothing is recorded when the file cannot be written
+        let missing_dir = dir.path().join("missing");
+        let unwritable = known_hosts::KnownHosts::open(&missing_dir.join("hosts"))?;
+        assert!(unwritable.check("localhost", &reflashed).is_err());
+        assert!(unwritable.get("localhost").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_log_writes_nss_lines() -> anyhow::Result<()> {
+        use rustls::KeyLog;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("key_log");
+        let key_log = keylog::KeyLogging::Path(path.clone()).open()?;
+        key_log.log("CLIENT_HANDSHAKE_TRAFFIC_SECRET", &[0x01, 0xab], &[0xff]);
+        key_log.log("SERVER_HANDSHAKE_TRAFFIC_SECRET", &[0x01, 0xab], &[0x00]);
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET 01ab ff\nSERVER_HANDSHAKE_TRAFFIC_SECRET 01ab 00\n"
+        );
+        assert!(keylog::KeyLogging::Path(path).s2n_path().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_crl_rejects_revoked_client() -> anyhow::Result<()> {
+        use rustls::server::ClientCertVerifier;
+
+        let ca = common::CertificateBuilder::new()
+            .common_name("test crl ca")
+            .certificate_authority()
+            .build()?;
+        let (revoked, _) = common::CertificateBuilder::new()
+            .dns_name("lost-laptop")
+            .generate_signed_by(&ca)?;
+        let (kept, _) = common::CertificateBuilder::new()
+            .dns_name("laptop")
+            .generate_signed_by(&ca)?;
+
+        let (_, parsed) = x509_parser::parse_x509_certificate(&revoked.0)?;
+        let now = time::OffsetDateTime::now_utc();
+        let crl = rcgen::CertificateRevocationList::from_params(
+            rcgen::CertificateRevocationListParams {
+                this_update: now - time::Duration::days(1),
+                next_update: now + time::Duration::days(1),
+                crl_number: rcgen::SerialNumber::from_slice(&[1]),
+                issuing_distribution_point: None,
+                revoked_certs: vec![rcgen::RevokedCertParams {
+                    serial_number: rcgen::SerialNumber::from_slice(
+                        parsed.tbs_certificate.raw_serial(),
+                    ),
+                    revocation_time: now,
+                    reason_code: Some(rcgen::RevocationReason::KeyCompromise),
+                    invalidity_date: None,
+                }],
+                alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+                key_identifier_method: rcgen::KeyIdMethod::Sha256,
+            },
+        )?;
+        let dir = tempfile::tempdir()?;
+        let crl_path = dir.path().join("crl.pem");
+        std::fs::write(&crl_path, crl.serialize_pem_with_signer(&ca)?)?;
+
+        let trust =
+            trust::TrustStore::new().with_certificate(rustls::Certificate(ca.serialize_der()?));
+        let crls = crl::RevocationList::open(&[&crl_path])?;
+        let verifier = crl::CrlClientVerifier::new(&trust, crls.clone())?;
+        let now = std::time::SystemTime::now();
+        assert!(verifier.verify_client_cert(&kept, &[], now).is_ok());
+        assert!(matches!(
+            verifier.verify_client_cert(&revoked, &[], now),
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::Revoked
+            ))
+        ));
+
+        // a custom verifier would silently skip the CRLs
+        let custom = rustls::client::WebPkiVerifier::new(trust.root_store()?, None);
+        let client = crate::quic::client::ClientTlsConfig::new(trust)
+            .with_crls(crls)
+            .with_verifier(std::sync::Arc::new(custom));
+        assert!(client.rustls_config().is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_signer() -> anyhow::Result<()> {
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let (other, _) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("signer.sock");
+        let daemon = signer::serve_signer(
+            &socket,
+            std::sync::Arc::new(signer::LocalSigner::new(&key)?),
+        )?;
+
+        let result = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let remote: std::sync::Arc<dyn signer::Signer> =
+                std::sync::Arc::new(signer::UnixSocketSigner::connect(&socket)?);
+            signer::certified_key_with_signer(vec![cert], remote.clone())?;
+            assert!(signer::certified_key_with_signer(vec![other], remote).is_err());
+            Ok(())
+        })
+        .await?;
+        daemon.abort();
+        result
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unix_socket_signer_on_runtime() -> anyhow::Result<()> {
+        let (cert, key) = common::CertificateBuilder::new()
+            .dns_name("localhost")
+            .generate()?;
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("signer.sock");
+        let daemon = signer::serve_signer(
+            &socket,
+            std::sync::Arc::new(signer::LocalSigner::new(&key)?),
+        )?;
+
+        // signing from a worker thread, as a handshake does
+        let remote = std::sync::Arc::new(signer::UnixSocketSigner::connect(&socket)?);
+        let certified = signer::certified_key_with_signer(vec![cert], remote.clone())?;
+        let scheme = certified
+            .key
+            .choose_scheme(&[rustls::SignatureScheme::ECDSA_NISTP256_SHA256])
+            .expect("daemon holds an ECDSA key");
+        assert!(!scheme.sign(b"message")?.is_empty());
+
+        daemon.abort();
+        let _ = daemon.await;
+        drop(certified);
+        let remote = (*remote)
+            .clone()
+            .timeout(std::time::Duration::from_millis(200));
+        assert!(signer::Signer::sign(
+            &remote,
+            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+            b"message"
+        )
+        .is_err());
+        Ok(())
+    }
+}
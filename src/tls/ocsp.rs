@@ -0,0 +1,227 @@
+use anyhow::Result;
+use der::{
+    asn1::{Any, BitString, GeneralizedTime, Int, ObjectIdentifier, OctetString},
+    Decode, Enumerated, Sequence, Tag, TagNumber, Tagged,
+};
+use rustls::Certificate;
+use std::{path::Path, time::SystemTime};
+
+const ID_PKIX_OCSP_BASIC: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.48.1.1");
+const ID_SHA1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.2");
+const ID_SHA512: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OcspStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// a DER OCSP response as fetched from the issuer, e.g. with `openssl ocsp -respout`
+///
+/// only the fields needed to decide whether to staple it are parsed, the client is
+/// responsible for verifying the responder's signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcspResponse {
+    pub der: Vec<u8>,
+    pub status: OcspStatus,
+    pub this_update: SystemTime,
+    pub next_update: Option<SystemTime>,
+}
+
+impl OcspResponse {
+    /// parse `der` and take the response for the leaf of `chain`
+    ///
+    /// fails if no response's CertID names the leaf's serial and issuer. the issuer key
+    /// hash is checked when the chain includes the issuer or the leaf is self-signed.
+    pub fn parse(der: Vec<u8>, chain: &[Certificate]) -> Result<Self> {
+        let response = OcspResponseAsn1::from_der(&der)
+            .map_err(|e| anyhow::anyhow!("malformed OCSP response: {e}"))?;
+        if response.response_status != ResponseStatus::Successful {
+            return Err(anyhow::anyhow!(
+                "OCSP responder returned status {:?}",
+                response.response_status
+            ));
+        }
+        let bytes = response
+            .response_bytes
+            .ok_or_else(|| anyhow::anyhow!("OCSP response has no responseBytes"))?;
+        if bytes.response_type != ID_PKIX_OCSP_BASIC {
+            return Err(anyhow::anyhow!(
+                "unsupported OCSP response type {}",
+                bytes.response_type
+            ));
+        }
+        let basic = BasicOcspResponse::from_der(bytes.response.as_bytes())
+            .map_err(|e| anyhow::anyhow!("malformed basic OCSP response: {e}"))?;
+
+        let leaf = CertIdSubject::from_chain(chain)?;
+        let single = basic
+            .tbs_response_data
+            .responses
+            .iter()
+            .find(|single| leaf.matches(&single.cert_id))
+            .ok_or_else(|| anyhow::anyhow!("OCSP response is not for the served certificate"))?;
+        let status = match single.cert_status.tag() {
+            Tag::ContextSpecific { number, .. } if number == TagNumber::N0 => OcspStatus::Good,
+            Tag::ContextSpecific { number, .. } if number == TagNumber::N1 => OcspStatus::Revoked,
+            _ => OcspStatus::Unknown,
+        };
+        Ok(Self {
+            status,
+            this_update: single.this_update.to_system_time(),
+            next_update: single.next_update.map(|t| t.to_system_time()),
+            der,
+        })
+    }
+
+    /// true once `nextUpdate` has passed and the responder should have been asked again
+    pub fn is_stale(&self) -> bool {
+        self.next_update
+            .map(|next| next <= SystemTime::now())
+            .unwrap_or(false)
+    }
+}
+
+/// read the response in `path` for the leaf of `chain`, see `OcspResponse::parse`
+pub fn read_ocsp_response(path: &Path, chain: &[Certificate]) -> Result<OcspResponse> {
+    OcspResponse::parse(std::fs::read(path)?, chain)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+/// the parts of a certificate an OCSP CertID is derived from
+struct CertIdSubject {
+    serial: Vec<u8>,
+    issuer_name: Vec<u8>,
+    issuer_key: Option<Vec<u8>>,
+}
+
+impl CertIdSubject {
+    fn from_chain(chain: &[Certificate]) -> Result<Self> {
+        let leaf = parse(
+            chain
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("certificate chain is empty"))?,
+        )?;
+        let issuer = match chain.get(1) {
+            Some(issuer) => Some(parse(issuer)?),
+            None => None,
+        };
+        let issuer_key = match issuer.as_ref() {
+            Some(issuer) => Some(issuer.public_key().subject_public_key.data.to_vec()),
+            None if leaf.subject().as_raw() == leaf.issuer().as_raw() => {
+                Some(leaf.public_key().subject_public_key.data.to_vec())
+            }
+            None => None,
+        };
+        Ok(Self {
+            serial: leaf.tbs_certificate.raw_serial().to_vec(),
+            issuer_name: leaf.issuer().as_raw().to_vec(),
+            issuer_key,
+        })
+    }
+
+    fn matches(&self, id: &CertId) -> bool {
+        let Some(digest) = digest_for(&id.hash_algorithm.algorithm) else {
+            return false;
+        };
+        let hash = |data: &[u8]| ring::digest::digest(digest, data).as_ref().to_vec();
+        id.serial_number.as_bytes() == self.serial
+            && id.issuer_name_hash.as_bytes() == hash(&self.issuer_name)
+            && self
+                .issuer_key
+                .as_ref()
+                .is_none_or(|key| id.issuer_key_hash.as_bytes() == hash(key))
+    }
+}
+
+fn parse(cert: &Certificate) -> Result<x509_parser::certificate::X509Certificate<'_>> {
+    x509_parser::parse_x509_certificate(&cert.0)
+        .map(|(_, parsed)| parsed)
+        .map_err(|e| anyhow::anyhow!("failed to parse certificate: {}", e))
+}
+
+fn digest_for(oid: &ObjectIdentifier) -> Option<&'static ring::digest::Algorithm> {
+    match *oid {
+        ID_SHA1 => Some(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY),
+        ID_SHA256 => Some(&ring::digest::SHA256),
+        ID_SHA384 => Some(&ring::digest::SHA384),
+        ID_SHA512 => Some(&ring::digest::SHA512),
+        _ => None,
+    }
+}
+
+// RFC 6960 types, fields the stapler does not look at are kept as `Any`
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enumerated)]
+#[repr(u32)]
+pub(crate) enum ResponseStatus {
+    Successful = 0,
+    MalformedRequest = 1,
+    InternalError = 2,
+    TryLater = 3,
+    SigRequired = 5,
+    Unauthorized = 6,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct OcspResponseAsn1 {
+    pub(crate) response_status: ResponseStatus,
+    #[asn1(context_specific = "0", optional = "true")]
+    pub(crate) response_bytes: Option<ResponseBytes>,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct ResponseBytes {
+    pub(crate) response_type: ObjectIdentifier,
+    pub(crate) response: OctetString,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct BasicOcspResponse {
+    pub(crate) tbs_response_data: ResponseData,
+    pub(crate) signature_algorithm: AlgorithmIdentifier,
+    pub(crate) signature: BitString,
+    #[asn1(context_specific = "0", optional = "true")]
+    pub(crate) certs: Option<Any>,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct ResponseData {
+    #[asn1(context_specific = "0", optional = "true")]
+    pub(crate) version: Option<u8>,
+    /// `[1] Name` or `[2] KeyHash`
+    pub(crate) responder_id: Any,
+    pub(crate) produced_at: GeneralizedTime,
+    pub(crate) responses: Vec<SingleResponse>,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) response_extensions: Option<Any>,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct SingleResponse {
+    pub(crate) cert_id: CertId,
+    /// `[0] good`, `[1] revoked` or `[2] unknown`
+    pub(crate) cert_status: Any,
+    pub(crate) this_update: GeneralizedTime,
+    #[asn1(context_specific = "0", optional = "true")]
+    pub(crate) next_update: Option<GeneralizedTime>,
+    #[asn1(context_specific = "1", optional = "true")]
+    pub(crate) single_extensions: Option<Any>,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct CertId {
+    pub(crate) hash_algorithm: AlgorithmIdentifier,
+    pub(crate) issuer_name_hash: OctetString,
+    pub(crate) issuer_key_hash: OctetString,
+    pub(crate) serial_number: Int,
+}
+
+#[derive(Debug, Clone, Sequence)]
+pub(crate) struct AlgorithmIdentifier {
+    pub(crate) algorithm: ObjectIdentifier,
+    pub(crate) parameters: Option<Any>,
+}
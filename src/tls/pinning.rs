@@ -0,0 +1,170 @@
+use anyhow::Result;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, ServerName,
+};
+use std::{fmt, str::FromStr, sync::Arc, time::SystemTime};
+
+use super::{from_hex, sha256, spki, to_hex, trust::TrustStore};
+
+/// sha256 digest identifying a server certificate
+///
+/// parsed from and displayed as `spki-sha256:<hex>` or `sha256:<hex>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pin {
+    /// hash of the SubjectPublicKeyInfo, survives re-issuing a cert for the same key
+    Spki([u8; 32]),
+    /// fingerprint of the whole leaf certificate
+    Leaf([u8; 32]),
+}
+
+impl Pin {
+    pub fn spki_of(cert: &Certificate) -> Result<Self> {
+        Ok(Pin::Spki(sha256(&spki(cert)?)))
+    }
+
+    pub fn leaf_of(cert: &Certificate) -> Self {
+        Pin::Leaf(sha256(&cert.0))
+    }
+
+    pub fn matches(&self, cert: &Certificate) -> bool {
+        match self {
+            Pin::Spki(digest) => spki(cert).is_ok_and(|spki| sha256(&spki) == *digest),
+            Pin::Leaf(digest) => sha256(&cert.0) == *digest,
+        }
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Spki(digest) => write!(f, "spki-sha256:{}", to_hex(digest)),
+            Pin::Leaf(digest) => write!(f, "sha256:{}", to_hex(digest)),
+        }
+    }
+}
+
+impl FromStr for Pin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, digest) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("pin {s:?} must look like spki-sha256:<hex>"))?;
+        let digest: [u8; 32] = from_hex(digest)
+            .and_then(|d| d.try_into().ok())
+            .ok_or_else(|| anyhow::anyhow!("pin {s:?} is not a hex sha256 digest"))?;
+        match kind {
+            "spki-sha256" => Ok(Pin::Spki(digest)),
+            "sha256" => Ok(Pin::Leaf(digest)),
+            _ => Err(anyhow::anyhow!("unknown pin type {kind:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinMode {
+    /// reject servers that match none of the pins
+    Enforce,
+    /// log mismatches and rely on chain validation alone
+    ReportOnly,
+}
+
+/// server verifier that checks the leaf against a set of pins
+///
+/// several pins can be active at once so a new key can be pinned before it is deployed.
+pub struct PinningVerifier {
+    pins: Vec<Pin>,
+    mode: PinMode,
+    chain: Option<WebPkiVerifier>,
+}
+
+impl PinningVerifier {
+    /// accept any certificate matching one of `pins`, without checking who issued it
+    pub fn new(pins: Vec<Pin>) -> Self {
+        Self {
+            pins,
+            mode: PinMode::Enforce,
+            chain: None,
+        }
+    }
+
+    /// also require a valid chain to one of the roots in `trust`
+    pub fn with_trust(mut self, trust: &TrustStore) -> Result<Self> {
+        self.chain = Some(WebPkiVerifier::new(trust.root_store()?, None));
+        Ok(self)
+    }
+
+    /// only log pin mismatches, chain validation against `trust` decides the handshake
+    pub fn report_only(self, trust: &TrustStore) -> Result<Self> {
+        let mut verifier = self.with_trust(trust)?;
+        verifier.mode = PinMode::ReportOnly;
+        Ok(verifier)
+    }
+
+    pub fn mode(&self) -> PinMode {
+        self.mode
+    }
+
+    /// client config with safe defaults for TLS over TCP
+    pub fn client_config(self) -> ClientConfig {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(self))
+            .with_no_client_auth()
+    }
+
+    pub fn tls_connector(self) -> tokio_rustls::TlsConnector {
+        tokio_rustls::TlsConnector::from(Arc::new(self.client_config()))
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain) = self.chain.as_ref() {
+            chain.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if self.pins.iter().any(|pin| pin.matches(end_entity)) {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        let presented = Pin::spki_of(end_entity)
+            .map(|pin| pin.to_string())
+            .unwrap_or_else(|_| Pin::leaf_of(end_entity).to_string());
+        match self.mode {
+            PinMode::Enforce => {
+                tracing::error!(
+                    "certificate pin mismatch for {:?}, presented {}",
+                    server_name,
+                    presented
+                );
+                Err(rustls::Error::General(format!(
+                    "certificate for {server_name:?} does not match any pin, presented {presented}"
+                )))
+            }
+            PinMode::ReportOnly => {
+                tracing::warn!(
+                    "certificate pin mismatch for {:?}, presented {} (report only)",
+                    server_name,
+                    presented
+                );
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::Result;
+use rustls::{
+    sign::{CertifiedKey, SigningKey},
+    Certificate, PrivateKey, SignatureAlgorithm, SignatureScheme,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+#[cfg(unix)]
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    sync::mpsc,
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixListener,
+    task::JoinHandle,
+};
+
+use crate::quic::common::{self, Passphrase};
+
+const SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA512,
+];
+
+/// produces handshake signatures for a private key that may live elsewhere
+pub trait Signer: Send + Sync + fmt::Debug {
+    /// schemes the key can sign with, in order of preference
+    fn schemes(&self) -> Vec<SignatureScheme>;
+
+    fn algorithm(&self) -> SignatureAlgorithm;
+
+    fn sign(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// signer holding the key in this process, what `read_key` based configs use
+pub struct LocalSigner {
+    key: Arc<dyn SigningKey>,
+}
+
+impl LocalSigner {
+    pub fn new(key: &PrivateKey) -> Result<Self> {
+        let key = rustls::sign::any_supported_type(key)
+            .map_err(|_| anyhow::anyhow!("unsupported private key type"))?;
+        Ok(Self { key })
+    }
+
+    pub fn from_file(key_path: &Path, passphrase: Option<&Passphrase>) -> Result<Self> {
+        Self::new(&common::read_key_with_passphrase(key_path, passphrase)?)
+    }
+}
+
+impl fmt::Debug for LocalSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalSigner")
+            .field("algorithm", &self.key.algorithm())
+            .finish()
+    }
+}
+
+impl Signer for LocalSigner {
+    fn schemes(&self) -> Vec<SignatureScheme> {
+        SCHEMES
+            .iter()
+            .copied()
+            .filter(|scheme| self.key.choose_scheme(&[*scheme]).is_some())
+            .collect()
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.key.algorithm()
+    }
+
+    fn sign(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>> {
+        let signer = self
+            .key
+            .choose_scheme(&[scheme])
+            .ok_or_else(|| anyhow::anyhow!("key cannot sign with {:?}", scheme))?;
+        Ok(signer.sign(message)?)
+    }
+}
+
+#[cfg(unix)]
+const OP_DESCRIBE: u8 = 1;
+#[cfg(unix)]
+const OP_SIGN: u8 = 2;
+#[cfg(unix)]
+const STATUS_OK: u8 = 0;
+#[cfg(unix)]
+const MAX_FRAME: usize = 64 * 1024;
+
+/// signer that forwards every signature to a daemon on a Unix socket
+///
+/// requests are `op: u8, scheme: u16, len: u32, message`, responses are
+/// `status: u8, len: u32, payload`, all big endian. a connection is opened per request
+/// so the daemon can be restarted at any time. `serve_signer` implements the daemon side.
+///
+/// the socket round-trip runs on a dedicated thread. rustls signs synchronously, so the
+/// handshake still waits up to `timeout` for the answer: on a multi-threaded tokio runtime
+/// the wait goes through `block_in_place`, elsewhere it blocks the calling thread, and a
+/// daemon served by the same current-thread runtime can only be used from
+/// `spawn_blocking`.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixSocketSigner {
+    path: PathBuf,
+    timeout: Duration,
+    schemes: Vec<SignatureScheme>,
+    algorithm: SignatureAlgorithm,
+    requests: mpsc::Sender<SignerRequest>,
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+struct SignerRequest {
+    op: u8,
+    scheme: u16,
+    message: Vec<u8>,
+    timeout: Duration,
+    reply: mpsc::SyncSender<Result<Vec<u8>>>,
+}
+
+#[cfg(unix)]
+impl UnixSocketSigner {
+    /// connect to the daemon and ask which key it holds
+    pub fn connect(path: &Path) -> Result<Self> {
+        let (requests, receiver) = mpsc::channel::<SignerRequest>();
+        let socket = path.to_path_buf();
+        std::thread::Builder::new()
+            .name("unix-socket-signer".to_string())
+            .spawn(move || {
+                // exits once every clone of the signer is dropped
+                for request in receiver {
+                    let result = round_trip(&socket, &request);
+                    let _ = request.reply.send(result);
+                }
+            })?;
+        let mut signer = Self {
+            path: path.to_path_buf(),
+            timeout: Duration::from_secs(5),
+            schemes: Vec::new(),
+            algorithm: SignatureAlgorithm::Anonymous,
+            requests,
+        };
+        let description = signer.request(OP_DESCRIBE, 0, &[])?;
+        let (&algorithm, schemes) = description
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("empty description from signer"))?;
+        signer.algorithm = SignatureAlgorithm::from(algorithm);
+        signer.schemes = schemes
+            .chunks_exact(2)
+            .map(|s| SignatureScheme::from(u16::from_be_bytes([s[0], s[1]])))
+            .collect();
+        Ok(signer)
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// hand the request to the signer thread and wait for its answer
+    fn request(&self, op: u8, scheme: u16, message: &[u8]) -> Result<Vec<u8>> {
+        let (reply, response) = mpsc::sync_channel(1);
+        self.requests
+            .send(SignerRequest {
+                op,
+                scheme,
+                message: message.to_vec(),
+                timeout: self.timeout,
+                reply,
+            })
+            .map_err(|_| anyhow::anyhow!("signer thread for {} exited", self.path.display()))?;
+        // the thread may still be finishing an earlier request that timed out here
+        let wait = || {
+            response
+                .recv_timeout(self.timeout * 2)
+                .map_err(|_| anyhow::anyhow!("signer at {} timed out", self.path.display()))?
+        };
+        match tokio::runtime::Handle::try_current().map(|handle| handle.runtime_flavor()) {
+            Ok(tokio::runtime::RuntimeFlavor::MultiThread) => tokio::task::block_in_place(wait),
+            _ => wait(),
+        }
+    }
+}
+
+/// one request and response on a fresh connection to the daemon at `path`
+#[cfg(unix)]
+fn round_trip(path: &Path, request: &SignerRequest) -> Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(request.timeout))?;
+    stream.set_write_timeout(Some(request.timeout))?;
+
+    let mut frame = vec![request.op];
+    frame.extend(request.scheme.to_be_bytes());
+    frame.extend((request.message.len() as u32).to_be_bytes());
+    frame.extend(&request.message);
+    stream.write_all(&frame)?;
+
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME {
+        return Err(anyhow::anyhow!("signer response too large"));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    if header[0] != STATUS_OK {
+        return Err(anyhow::anyhow!(
+            "signer at {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&payload)
+        ));
+    }
+    Ok(payload)
+}
+
+#[cfg(unix)]
+impl Signer for UnixSocketSigner {
+    fn schemes(&self) -> Vec<SignatureScheme> {
+        self.schemes.clone()
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
+
+    fn sign(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>> {
+        self.request(OP_SIGN, scheme.get_u16(), message)
+    }
+}
+
+/// answer `UnixSocketSigner` requests on `path` with `signer`
+///
+/// an existing socket file at `path` is replaced.
+#[cfg(unix)]
+pub fn serve_signer(path: &Path, signer: Arc<dyn Signer>) -> Result<JoinHandle<()>> {
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    Ok(tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // errors like EMFILE persist until connections close, don't spin on them
+                    tracing::error!("signer accept failed {:?}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let signer = signer.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_request(stream, signer).await {
+                    tracing::error!("signer request failed {:?}", e);
+                }
+            });
+        }
+    }))
+}
+
+#[cfg(unix)]
+async fn handle_request(mut stream: tokio::net::UnixStream, signer: Arc<dyn Signer>) -> Result<()> {
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await?;
+    let len = u32::from_be_bytes([header[3], header[4], header[5], header[6]]) as usize;
+    if len > MAX_FRAME {
+        return Err(anyhow::anyhow!("signing request too large"));
+    }
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message).await?;
+
+    let result = match header[0] {
+        OP_DESCRIBE => {
+            let mut description = vec![signer.algorithm().get_u8()];
+            for scheme in signer.schemes() {
+                description.extend(scheme.get_u16().to_be_bytes());
+            }
+            Ok(description)
+        }
+        OP_SIGN => {
+            let scheme = SignatureScheme::from(u16::from_be_bytes([header[1], header[2]]));
+            let signer = signer.clone();
+            tokio::task::spawn_blocking(move || signer.sign(scheme, &message)).await?
+        }
+        op => Err(anyhow::anyhow!("unknown signer op {}", op)),
+    };
+    let (status, payload) = match result {
+        Ok(payload) => (STATUS_OK, payload),
+        Err(e) => (1, e.to_string().into_bytes()),
+    };
+    let mut response = vec![status];
+    response.extend((payload.len() as u32).to_be_bytes());
+    response.extend(payload);
+    stream.write_all(&response).await?;
+    Ok(())
+}
+
+/// adapts a `Signer` to the rustls signing key interface
+struct SignerKey(Arc<dyn Signer>);
+
+impl SigningKey for SignerKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn rustls::sign::Signer>> {
+        let scheme = self
+            .0
+            .schemes()
+            .into_iter()
+            .find(|scheme| offered.contains(scheme))?;
+        Some(Box::new(SchemeSigner {
+            signer: self.0.clone(),
+            scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.0.algorithm()
+    }
+}
+
+struct SchemeSigner {
+    signer: Arc<dyn Signer>,
+    scheme: SignatureScheme,
+}
+
+impl rustls::sign::Signer for SchemeSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        self.signer.sign(self.scheme, message).map_err(|e| {
+            tracing::error!("handshake signature failed {:?}", e);
+            rustls::Error::General(format!("signer failed: {e}"))
+        })
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// pair `certs` with `signer`, checking the signer holds the leaf's key
+pub fn certified_key_with_signer(
+    certs: Vec<Certificate>,
+    signer: Arc<dyn Signer>,
+) -> Result<CertifiedKey> {
+    let certified = CertifiedKey::new(certs, Arc::new(SignerKey(signer)));
+    common::verify_certified_key(&certified)?;
+    Ok(certified)
+}
+
+/// where a certificate's private key comes from
+#[derive(Debug, Clone)]
+pub(crate) enum KeySource {
+    File {
+        path: PathBuf,
+        passphrase: Option<Passphrase>,
+    },
+    Signer(Arc<dyn Signer>),
+}
+
+impl KeySource {
+    pub(crate) fn file(path: &Path) -> Self {
+        KeySource::File {
+            path: path.to_path_buf(),
+            passphrase: None,
+        }
+    }
+
+    /// load the chain in `cert_path` and pair it with this key
+    pub(crate) fn certified_key(&self, cert_path: &Path) -> Result<CertifiedKey> {
+        match self {
+            KeySource::File { path, passphrase } => {
+                common::load_certified_key(cert_path, path, passphrase.as_ref())
+            }
+            KeySource::Signer(signer) => {
+                let certs = common::read_cert_chain(cert_path)?
+                    .into_iter()
+                    .map(Certificate)
+                    .collect();
+                certified_key_with_signer(certs, signer.clone())
+            }
+        }
+    }
+}
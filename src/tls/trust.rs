@@ -0,0 +1,136 @@
+use anyhow::Result;
+use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::quic::common;
+
+/// set of roots used to verify servers
+///
+/// sources are combined, so a store can trust the bundled webpki roots, the roots
+/// installed on the system and any number of private CAs at the same time.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    webpki_roots: bool,
+    system_roots: bool,
+    ca_files: Vec<PathBuf>,
+    certificates: Vec<rustls::Certificate>,
+}
+
+impl TrustStore {
+    /// an empty store, add at least one source before use
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// trust only the CAs in `path`, the equivalent of the `cert_pem_path` clients take
+    pub fn from_ca_file(path: &Path) -> Self {
+        Self::new().with_ca_file(path)
+    }
+
+    /// the Mozilla roots bundled with `webpki-roots`
+    pub fn with_webpki_roots(mut self) -> Self {
+        self.webpki_roots = true;
+        self
+    }
+
+    /// roots from the operating system store, e.g. `/etc/ssl/certs` on Linux
+    pub fn with_system_roots(mut self) -> Self {
+        self.system_roots = true;
+        self
+    }
+
+    /// every certificate in a PEM, DER or PKCS#12 file
+    pub fn with_ca_file(mut self, path: &Path) -> Self {
+        self.ca_files.push(path.to_path_buf());
+        self
+    }
+
+    pub fn with_certificate(mut self, cert: rustls::Certificate) -> Self {
+        self.certificates.push(cert);
+        self
+    }
+
+    pub(crate) fn uses_webpki_roots(&self) -> bool {
+        self.webpki_roots
+    }
+
+    pub(crate) fn uses_system_roots(&self) -> bool {
+        self.system_roots
+    }
+
+    pub(crate) fn ca_files(&self) -> &[PathBuf] {
+        &self.ca_files
+    }
+
+    pub(crate) fn certificates(&self) -> &[rustls::Certificate] {
+        &self.certificates
+    }
+
+    /// DER certificates from every source other than the bundled webpki roots
+    pub(crate) fn certificate_ders(&self) -> Result<Vec<Vec<u8>>> {
+        let mut ders = Vec::new();
+        if self.system_roots {
+            ders.extend(
+                rustls_native_certs::load_native_certs()?
+                    .into_iter()
+                    .map(|cert| cert.0),
+            );
+        }
+        for path in self.ca_files.iter() {
+            ders.extend(common::read_cert_chain(path)?);
+        }
+        ders.extend(self.certificates.iter().map(|cert| cert.0.clone()));
+        Ok(ders)
+    }
+
+    pub fn root_store(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        if self.webpki_roots {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+        if self.system_roots {
+            let certs = rustls_native_certs::load_native_certs()?;
+            let (added, ignored) = roots.add_parsable_certificates(
+                &certs.into_iter().map(|cert| cert.0).collect::<Vec<_>>(),
+            );
+            tracing::debug!("loaded {} system roots, ignored {}", added, ignored);
+        }
+        for path in self.ca_files.iter() {
+            for cert in common::read_cert_chain(path)? {
+                roots.add(&rustls::Certificate(cert)).map_err(|e| {
+                    anyhow::anyhow!("invalid CA certificate in {}: {}", path.display(), e)
+                })?;
+            }
+        }
+        for cert in self.certificates.iter() {
+            roots.add(cert)?;
+        }
+        if roots.is_empty() {
+            return Err(anyhow::anyhow!("trust store has no roots"));
+        }
+        Ok(roots)
+    }
+
+    /// client config with safe defaults for TLS over TCP
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        Ok(ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_store()?)
+            .with_no_client_auth())
+    }
+
+    pub fn tls_connector(&self) -> Result<tokio_rustls::TlsConnector> {
+        Ok(tokio_rustls::TlsConnector::from(Arc::new(
+            self.client_config()?,
+        )))
+    }
+}
@@ -0,0 +1,650 @@
+use anyhow::Result;
+use http::HeaderValue;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use serde::Deserialize;
+use std::{
+    ops::RangeInclusive,
+    path::Path,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::utils::{get_random_int, with_current_rng, USER_AGENTS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Browser {
+    Chrome,
+    Edge,
+    Opera,
+    Firefox,
+    Safari,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Os {
+    Windows,
+    MacOs,
+    Linux,
+    ChromeOs,
+    Android,
+    Ios,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    Desktop,
+    Mobile,
+    Tablet,
+}
+
+/// what a user agent string claims to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserAgentInfo {
+    pub browser: Browser,
+    /// major version of the browser, 0 when unknown
+    pub version: u32,
+    pub os: Os,
+    pub device: DeviceClass,
+}
+
+impl UserAgentInfo {
+    pub fn parse(user_agent: &str) -> Self {
+        let version_of = |product| product_version(user_agent, product);
+        let (browser, version) = if let Some(v) = version_of("Edg/") {
+            (Browser::Edge, Some(v))
+        } else if let Some(v) = version_of("OPR/") {
+            (Browser::Opera, Some(v))
+        } else if let Some(v) = version_of("Chrome/").or_else(|| version_of("CriOS/")) {
+            (Browser::Chrome, Some(v))
+        } else if let Some(v) = version_of("Firefox/").or_else(|| version_of("FxiOS/")) {
+            (Browser::Firefox, Some(v))
+        } else if user_agent.contains("Safari/") {
+            (Browser::Safari, version_of("Version/"))
+        } else {
+            (Browser::Other, None)
+        };
+
+        let os = if user_agent.contains("Windows") {
+            Os::Windows
+        } else if user_agent.contains("Android") {
+            Os::Android
+        } else if user_agent.contains("iPhone") || user_agent.contains("iPad") {
+            Os::Ios
+        } else if user_agent.contains("CrOS") {
+            Os::ChromeOs
+        } else if user_agent.contains("Macintosh") || user_agent.contains("Mac OS X") {
+            Os::MacOs
+        } else if user_agent.contains("Linux") || user_agent.contains("X11") {
+            Os::Linux
+        } else {
+            Os::Other
+        };
+
+        let device = if user_agent.contains("iPad")
+            || (os == Os::Android && !user_agent.contains("Mobile"))
+        {
+            DeviceClass::Tablet
+        } else if user_agent.contains("Mobile") || user_agent.contains("iPhone") {
+            DeviceClass::Mobile
+        } else {
+            DeviceClass::Desktop
+        };
+
+        Self {
+            browser,
+            version: version.unwrap_or(0),
+            os,
+            device,
+        }
+    }
+}
+
+/// major version following `product`, e.g. 101 for `Chrome/` in `Chrome/101.0.4951.67`
+pub(crate) fn product_version(user_agent: &str, product: &str) -> Option<u32> {
+    let (_, rest) = user_agent.split_once(product)?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentEntry {
+    /// shared with every caller that drew this entry
+    pub user_agent: Arc<str>,
+    /// relative likelihood of being chosen, e.g. market share
+    pub weight: f64,
+    pub info: UserAgentInfo,
+}
+
+impl UserAgentEntry {
+    /// fails unless `user_agent` is a non-empty header value and `weight` is positive
+    pub fn new(user_agent: &str, weight: f64) -> Result<Self> {
+        if user_agent.trim().is_empty() {
+            return Err(anyhow::anyhow!("user agent is empty"));
+        }
+        // `from_static` is stricter than `from_str`, accept what both take
+        if !user_agent
+            .bytes()
+            .all(|b| b == b'\t' || (0x20..0x7f).contains(&b))
+            || HeaderValue::from_str(user_agent).is_err()
+        {
+            return Err(anyhow::anyhow!(
+                "{:?} is not a valid header value",
+                user_agent
+            ));
+        }
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(anyhow::anyhow!(
+                "weight of {:?} must be positive, got {}",
+                user_agent,
+                weight
+            ));
+        }
+        Ok(Self {
+            user_agent: user_agent.into(),
+            weight,
+            info: UserAgentInfo::parse(user_agent),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonEntry {
+    user_agent: String,
+    #[serde(default = "default_weight")]
+    weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// which agents a `UserAgentDb::filter` keeps, empty lists allow everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserAgentFilter {
+    pub browsers: Vec<Browser>,
+    pub oses: Vec<Os>,
+    pub devices: Vec<DeviceClass>,
+    pub min_version: Option<u32>,
+}
+
+impl UserAgentFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_browser(mut self, browser: Browser) -> Self {
+        self.browsers.push(browser);
+        self
+    }
+
+    pub fn with_os(mut self, os: Os) -> Self {
+        self.oses.push(os);
+        self
+    }
+
+    pub fn with_device(mut self, device: DeviceClass) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    pub fn with_min_version(mut self, version: u32) -> Self {
+        self.min_version = Some(version);
+        self
+    }
+
+    pub fn matches(&self, info: &UserAgentInfo) -> bool {
+        (self.browsers.is_empty() || self.browsers.contains(&info.browser))
+            && (self.oses.is_empty() || self.oses.contains(&info.os))
+            && (self.devices.is_empty() || self.devices.contains(&info.device))
+            && self.min_version.is_none_or(|min| info.version >= min)
+    }
+}
+
+/// weighted list of user agents to pick from
+///
+/// loaded from JSON, an array of `{"user_agent": "...", "weight": 12.5}` with the weight
+/// defaulting to 1, or CSV with `weight,user_agent` per line. the user agent is the rest
+/// of the line so it may contain commas, a first line that does not start with a number
+/// is taken as a header.
+#[derive(Debug, Clone, Default)]
+pub struct UserAgentDb {
+    entries: Vec<UserAgentEntry>,
+    /// built once for `choose`, none when the list is empty
+    index: Option<WeightedIndex<f64>>,
+}
+
+impl PartialEq for UserAgentDb {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl UserAgentDb {
+    pub fn new(entries: Vec<UserAgentEntry>) -> Self {
+        let index = WeightedIndex::new(entries.iter().map(|e| e.weight)).ok();
+        Self { entries, index }
+    }
+
+    /// the list shipped with the crate, all weighted equally
+    pub fn builtin() -> Self {
+        Self::new(
+            USER_AGENTS
+                .iter()
+                .map(|ua| UserAgentEntry::new(ua, 1.0).expect("weight is positive"))
+                .collect(),
+        )
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        let entries: Vec<JsonEntry> = serde_json::from_str(json)?;
+        Ok(Self::new(
+            entries
+                .iter()
+                .map(|e| UserAgentEntry::new(&e.user_agent, e.weight))
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    pub fn from_csv_str(csv: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (i, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (weight, user_agent) = line
+                .split_once(',')
+                .ok_or_else(|| anyhow::anyhow!("line {}: expected weight,user_agent", i + 1))?;
+            let Ok(weight) = weight.trim().parse::<f64>() else {
+                if i == 0 {
+                    continue;
+                }
+                return Err(anyhow::anyhow!(
+                    "line {}: invalid weight {:?}",
+                    i + 1,
+                    weight
+                ));
+            };
+            let user_agent = user_agent.trim().trim_matches('"');
+            entries.push(UserAgentEntry::new(user_agent, weight)?);
+        }
+        Ok(Self::new(entries))
+    }
+
+    /// read `path` as CSV when it ends in `.csv` and as JSON otherwise
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let db = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Self::from_csv_str(&contents),
+            _ => Self::from_json_str(&contents),
+        };
+        db.map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn entries(&self) -> &[UserAgentEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn filter(&self, filter: &UserAgentFilter) -> Self {
+        Self::new(
+            self.entries
+                .iter()
+                .filter(|e| filter.matches(&e.info))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// a user agent chosen by weight, none if the list is empty
+    pub fn choose(&self) -> Option<Arc<str>> {
+        let index = self.index.as_ref()?;
+        Some(
+            self.entries[with_current_rng(|rng| index.sample(rng))]
+                .user_agent
+                .clone(),
+        )
+    }
+
+    /// like `choose` among the entries `predicate` accepts
+    ///
+    /// walks the list twice instead of building an index, `filter` once and `choose` on
+    /// the result when drawing from the same subset repeatedly.
+    pub fn choose_matching(&self, predicate: impl Fn(&UserAgentEntry) -> bool) -> Option<Arc<str>> {
+        let total: f64 = self
+            .entries
+            .iter()
+            .filter(|e| predicate(e))
+            .map(|e| e.weight)
+            .sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut point = with_current_rng(|rng| rng.gen_range(0.0..total));
+        let mut last = None;
+        for entry in self.entries.iter().filter(|e| predicate(e)) {
+            if point < entry.weight {
+                return Some(entry.user_agent.clone());
+            }
+            point -= entry.weight;
+            last = Some(&entry.user_agent);
+        }
+        // rounding left the point past the final weight
+        last.cloned()
+    }
+}
+
+const CHROME: &str = "Mozilla/5.0 ({platform}) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/{major}.0.0.0 Safari/537.36";
+const FIREFOX: &str = "Mozilla/5.0 ({platform}; rv:{major}.0) Gecko/20100101 Firefox/{major}.0";
+
+/// a user agent string with `{major}` and optionally `{minor}` left open
+///
+/// majors are drawn from `versions`, minors from 0 to 6, so both should be kept in line
+/// with what the browser currently ships.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentTemplate {
+    pub template: String,
+    pub versions: RangeInclusive<u32>,
+    pub weight: f64,
+    pub info: UserAgentInfo,
+}
+
+impl UserAgentTemplate {
+    pub fn new(template: &str, versions: RangeInclusive<u32>) -> Result<Self> {
+        if !template.contains("{major}") {
+            return Err(anyhow::anyhow!("template has no {{major}}: {:?}", template));
+        }
+        if versions.is_empty() {
+            return Err(anyhow::anyhow!("empty version range {:?}", versions));
+        }
+        let info =
+            UserAgentInfo::parse(&template.replace("{major}", &versions.start().to_string()));
+        Ok(Self {
+            template: template.to_string(),
+            versions,
+            weight: 1.0,
+            info,
+        })
+    }
+
+    /// fails unless `weight` is positive, like `UserAgentEntry::new`
+    pub fn with_weight(mut self, weight: f64) -> Result<Self> {
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(anyhow::anyhow!(
+                "weight of {:?} must be positive, got {}",
+                self.template,
+                weight
+            ));
+        }
+        self.weight = weight;
+        Ok(self)
+    }
+
+    pub fn render(&self, major: u32, minor: u32) -> String {
+        self.template
+            .replace("{major}", &major.to_string())
+            .replace("{minor}", &minor.to_string())
+    }
+
+    /// render with a random version from the range
+    pub fn generate(&self) -> String {
+        let major = get_random_int(
+            *self.versions.start() as usize,
+            *self.versions.end() as usize + 1,
+        );
+        self.render(major as u32, get_random_int(0, 7) as u32)
+    }
+}
+
+/// composes user agents from templates instead of a fixed list
+///
+/// the default templates cover current Chrome, Edge, Firefox and Safari on the common
+/// desktop and mobile platforms, weighted roughly by usage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentGenerator {
+    templates: Vec<UserAgentTemplate>,
+}
+
+impl Default for UserAgentGenerator {
+    fn default() -> Self {
+        let chrome = |platform: &str| CHROME.replace("{platform}", platform);
+        let firefox = |platform: &str| FIREFOX.replace("{platform}", platform);
+        let templates = [
+            (chrome("Windows NT 10.0; Win64; x64"), 138..=142, 40.0),
+            (chrome("Macintosh; Intel Mac OS X 10_15_7"), 138..=142, 10.0),
+            (chrome("X11; Linux x86_64"), 138..=142, 3.0),
+            (
+                "Mozilla/5.0 (Linux; Android 10; K) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/{major}.0.0.0 Mobile Safari/537.36".to_string(),
+                138..=142,
+                25.0,
+            ),
+            (
+                chrome("Windows NT 10.0; Win64; x64") + " Edg/{major}.0.0.0",
+                138..=142,
+                8.0,
+            ),
+            (firefox("Windows NT 10.0; Win64; x64"), 140..=144, 4.0),
+            (firefox("Macintosh; Intel Mac OS X 10.15"), 140..=144, 1.0),
+            (firefox("X11; Linux x86_64"), 140..=144, 2.0),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/{major}.{minor} Safari/605.1.15".to_string(),
+                17..=18,
+                4.0,
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS {major}_{minor} like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/{major}.{minor} Mobile/15E148 Safari/604.1".to_string(),
+                17..=18,
+                15.0,
+            ),
+        ];
+        Self::new(
+            templates
+                .into_iter()
+                .map(|(template, versions, weight)| {
+                    UserAgentTemplate::new(&template, versions)
+                        .and_then(|t| t.with_weight(weight))
+                        .expect("built-in templates are valid")
+                })
+                .collect(),
+        )
+    }
+}
+
+impl UserAgentGenerator {
+    pub fn new(templates: Vec<UserAgentTemplate>) -> Self {
+        Self { templates }
+    }
+
+    pub fn with_template(mut self, template: UserAgentTemplate) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    /// set the major versions used for every template of `browser`
+    pub fn with_versions(
+        mut self,
+        browser: Browser,
+        versions: RangeInclusive<u32>,
+    ) -> Result<Self> {
+        if versions.is_empty() {
+            return Err(anyhow::anyhow!("empty version range {:?}", versions));
+        }
+        for template in self.templates.iter_mut() {
+            if template.info.browser == browser {
+                template.versions = versions.clone();
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn filter(&self, filter: &UserAgentFilter) -> Self {
+        Self::new(
+            self.templates
+                .iter()
+                .filter(|t| filter.matches(&t.info))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    pub fn templates(&self) -> &[UserAgentTemplate] {
+        &self.templates
+    }
+
+    /// a user agent from a template chosen by weight, none without templates
+    pub fn generate(&self) -> Option<String> {
+        let index = WeightedIndex::new(self.templates.iter().map(|t| t.weight)).ok()?;
+        Some(self.templates[with_current_rng(|rng| index.sample(rng))].generate())
+    }
+
+    /// every version of every template as a list, weights split evenly across versions
+    pub fn to_db(&self) -> Result<UserAgentDb> {
+        let mut entries = Vec::new();
+        for template in self.templates.iter() {
+            let versions = template.versions.clone().count() as f64;
+            for major in template.versions.clone() {
+                entries.push(UserAgentEntry::new(
+                    &template.render(major, 0),
+                    template.weight / versions,
+                )?);
+            }
+        }
+        Ok(UserAgentDb::new(entries))
+    }
+}
+
+static INSTALLED: RwLock<Option<Arc<UserAgentDb>>> = RwLock::new(None);
+static BUILTIN: OnceLock<Arc<UserAgentDb>> = OnceLock::new();
+
+/// make `db` the list used by `get_random_usr_agent` and the other helpers
+///
+/// the replaced list is freed once no caller of `database` holds it anymore.
+pub fn install(db: UserAgentDb) -> Result<()> {
+    if db.is_empty() {
+        return Err(anyhow::anyhow!("user agent list is empty"));
+    }
+    *INSTALLED.write().expect("user agent lock poisoned") = Some(Arc::new(db));
+    Ok(())
+}
+
+/// the installed list, or the built-in one when nothing was installed
+pub fn database() -> Arc<UserAgentDb> {
+    let installed = INSTALLED.read().expect("user agent lock poisoned").clone();
+    installed.unwrap_or_else(|| {
+        BUILTIN
+            .get_or_init(|| Arc::new(UserAgentDb::builtin()))
+            .clone()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_user_agents() {
+        let info = UserAgentInfo::parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.67 Safari/537.36 Edg/101.0.1210.47");
+        assert_eq!(
+            (info.browser, info.version, info.os, info.device),
+            (Browser::Edge, 101, Os::Windows, DeviceClass::Desktop)
+        );
+        let info = UserAgentInfo::parse("Mozilla/5.0 (iPhone; CPU iPhone OS 15_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.5 Mobile/15E148 Safari/604.1");
+        assert_eq!(
+            (info.browser, info.version, info.os, info.device),
+            (Browser::Safari, 15, Os::Ios, DeviceClass::Mobile)
+        );
+        let info = UserAgentInfo::parse(
+            "Mozilla/5.0 (Android 12; Tablet; rv:101.0) Gecko/101.0 Firefox/101.0",
+        );
+        assert_eq!(
+            (info.browser, info.os, info.device),
+            (Browser::Firefox, Os::Android, DeviceClass::Tablet)
+        );
+    }
+
+    #[test]
+    fn test_load_weighted_user_agents() -> anyhow::Result<()> {
+        let csv = "weight,user_agent\n\
+            1000,Mozilla/5.0 (X11; Linux x86_64; rv:100.0) Gecko/20100101 Firefox/100.0\n\
+            0.001,\"Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.67 Safari/537.36\"\n";
+        let db = UserAgentDb::from_csv_str(csv)?;
+        assert_eq!(db.len(), 2);
+        let firefox = (0..100)
+            .filter(|_| db.choose().is_some_and(|ua| ua.contains("Firefox")))
+            .count();
+        assert!(firefox > 90);
+
+        let json = r#"[
+            {"user_agent": "Mozilla/5.0 (X11; Linux x86_64; rv:100.0) Gecko/20100101 Firefox/100.0"},
+            {"user_agent": "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.67 Safari/537.36", "weight": 2.5}
+        ]"#;
+        let db = UserAgentDb::from_json_str(json)?;
+        let chrome = db.filter(&UserAgentFilter::new().with_browser(Browser::Chrome));
+        assert_eq!(chrome.len(), 1);
+        assert_eq!(chrome.entries()[0].weight, 2.5);
+        assert!(db
+            .filter(&UserAgentFilter::new().with_min_version(102))
+            .is_empty());
+        assert!(UserAgentDb::from_json_str(r#"[{"user_agent": "x", "weight": 0}]"#).is_err());
+        assert!(UserAgentDb::from_json_str(r#"[{"user_agent": " "}]"#).is_err());
+        assert!(UserAgentDb::from_csv_str("1,Mozilla/5.0\u{7f}").is_err());
+        assert!(UserAgentDb::from_json_str(
+            r#"[{"user_agent": "Mozilla/5.0 (Linux) Firefox/100.0 caf\u00e9"}]"#
+        )
+        .is_err());
+        let firefox = db.filter(&UserAgentFilter::new().with_browser(Browser::Firefox));
+        for _ in 0..10 {
+            assert_eq!(
+                db.choose_matching(|e| e.info.browser == Browser::Firefox),
+                firefox.choose()
+            );
+        }
+        assert_eq!(db.choose_matching(|_| false), None);
+
+        let old = UserAgentDb::builtin()
+            .filter(&UserAgentFilter::new().with_browser(Browser::Firefox))
+            .filter(&UserAgentFilter::new().with_os(Os::Linux));
+        assert!(!old.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_user_agents() -> anyhow::Result<()> {
+        let generated = UserAgentGenerator::default()
+            .with_versions(Browser::Firefox, 150..=150)?
+            .filter(&UserAgentFilter::new().with_browser(Browser::Firefox));
+        for _ in 0..10 {
+            let ua = generated.generate().expect("firefox templates exist");
+            let info = UserAgentInfo::parse(&ua);
+            assert_eq!((info.browser, info.version), (Browser::Firefox, 150));
+        }
+        let db = UserAgentGenerator::default().to_db()?;
+        assert!(db
+            .entries()
+            .iter()
+            .all(|e| e.info.browser != Browser::Other));
+
+        assert!(UserAgentGenerator::default()
+            .with_versions(Browser::Chrome, RangeInclusive::new(150, 140))
+            .is_err());
+        let template = UserAgentTemplate::new(FIREFOX, 140..=144)?;
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(template.clone().with_weight(weight).is_err());
+        }
+        assert_eq!(template.with_weight(2.0)?.weight, 2.0);
+        Ok(())
+    }
+}
@@ -1,7 +1,20 @@
-use rand::Rng;
-use rustls::{SupportedKxGroup, SupportedProtocolVersion};
+use anyhow::Result;
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rustls::{
+    cipher_suite::*, client::ServerCertVerifier, ClientConfig, ConfigBuilder, SignatureAlgorithm,
+    SupportedCipherSuite, SupportedKxGroup, SupportedProtocolVersion, WantsVerifier,
+};
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+};
 
-const USER_AGENTS: [&str; 91] = [
+use crate::{
+    tls::trust::TrustStore,
+    user_agent::{self, product_version},
+};
+
+pub(crate) const USER_AGENTS: [&str; 91] = [
      "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.67 Safari/537.36",
             "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:100.0) Gecko/20100101 Firefox/100.0",
             "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/101.0.4951.54 Safari/537.36",
@@ -95,17 +108,108 @@ const USER_AGENTS: [&str; 91] = [
             "Mozilla/5.0 (X11; Ubuntu; Linux i686; rv:52.0) Gecko/20100101 Firefox/52.0",
 ];
 
+thread_local! {
+    static RNG: RefCell<Option<Box<dyn RngCore>>> = RefCell::new(None);
+}
+
+/// run `f` with every `get_random_*` helper on this thread drawing from `rng`
+///
+/// the previous source is restored afterwards, so calls can be nested.
+pub fn with_rng<R: RngCore + 'static, T>(rng: R, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Box<dyn RngCore>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            RNG.with(|rng| *rng.borrow_mut() = previous);
+        }
+    }
+    let _restore = Restore(RNG.with(|cell| cell.borrow_mut().replace(Box::new(rng))));
+    f()
+}
+
+/// like `with_rng` with a generator seeded from `seed`, the seed is logged so the same
+/// choices can be replayed
+///
+/// only choices made inside `f` on this thread are covered. futures and services draw
+/// when they are polled or called, so async entry points take their randomness up front
+/// and layers have their own seeded constructors.
+pub fn with_seed<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    tracing::debug!("random choices seeded with {}", seed);
+    with_rng(StdRng::seed_from_u64(seed), f)
+}
+
+/// a seeded generator that can be shared across threads, for randomness drawn later
+/// than the call that set it up
+#[derive(Debug, Clone)]
+pub(crate) struct SharedRng(Arc<Mutex<StdRng>>);
+
+impl SharedRng {
+    pub(crate) fn from_seed(seed: u64) -> Self {
+        tracing::debug!("random choices seeded with {}", seed);
+        Self(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+
+    /// run `f` with the `get_random_*` helpers drawing from this generator
+    pub(crate) fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        with_rng(self.clone(), f)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StdRng> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.lock().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.lock().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.lock().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.lock().try_fill_bytes(dest)
+    }
+}
+
+/// a seed for `with_seed`, itself drawn from the current source
+pub fn random_seed() -> u64 {
+    with_current_rng(|rng| rng.next_u64())
+}
+
+/// the rng installed by `with_rng`, or the thread rng
+pub(crate) fn with_current_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    RNG.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(rng) => f(rng.as_mut()),
+        None => f(&mut rand::thread_rng()),
+    })
+}
+
 #[inline(always)]
 pub(crate) fn get_random_int(start: usize, stop: usize) -> usize {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(start..stop)
+    with_current_rng(|rng| rng.gen_range(start..stop))
+}
+
+/// get a random user agent from the installed `UserAgentDb`, or the built-in list
+pub fn get_random_usr_agent() -> Arc<str> {
+    user_agent::database()
+        .choose()
+        .expect("user agent lists are never empty")
 }
 
-/// get a random user agent
-pub fn get_random_usr_agent() -> &'static str {
-    USER_AGENTS
-        .get(get_random_int(0, USER_AGENTS.len()))
-        .expect("unreachable, bounds of get match bounds of get_random_int")
+/// a freshly composed user agent for a current browser, see `UserAgentGenerator`
+pub fn get_random_synthesized_usr_agent() -> String {
+    static GENERATOR: std::sync::OnceLock<user_agent::UserAgentGenerator> =
+        std::sync::OnceLock::new();
+    GENERATOR
+        .get_or_init(user_agent::UserAgentGenerator::default)
+        .generate()
+        .expect("default templates are never empty")
 }
 
 pub fn get_random_ciphersuites(n: usize) -> Vec<rustls::SupportedCipherSuite> {
@@ -138,3 +242,393 @@ pub fn get_random_protocols() -> Vec<&'static SupportedProtocolVersion> {
         _ => protocols[0..3].to_vec(),
     }
 }
+
+/// the handshake parameters that make up a client's TLS fingerprint
+///
+/// order matters, suites and groups are offered in the order given.
+#[derive(Debug, Clone)]
+pub struct TlsProfile {
+    pub cipher_suites: Vec<SupportedCipherSuite>,
+    pub kx_groups: Vec<&'static SupportedKxGroup>,
+    pub protocol_versions: Vec<&'static SupportedProtocolVersion>,
+    /// protocols offered in ALPN, none by default
+    pub alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl Default for TlsProfile {
+    /// the rustls defaults
+    fn default() -> Self {
+        Self::new(
+            rustls::DEFAULT_CIPHER_SUITES.to_vec(),
+            rustls::ALL_KX_GROUPS.to_vec(),
+            rustls::DEFAULT_VERSIONS.to_vec(),
+        )
+    }
+}
+
+impl TlsProfile {
+    pub fn new(
+        cipher_suites: Vec<SupportedCipherSuite>,
+        kx_groups: Vec<&'static SupportedKxGroup>,
+        protocol_versions: Vec<&'static SupportedProtocolVersion>,
+    ) -> Self {
+        Self {
+            cipher_suites,
+            kx_groups,
+            protocol_versions,
+            alpn_protocols: Vec::new(),
+        }
+    }
+
+    pub fn with_alpn_protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.alpn_protocols = protocols.into_iter().map(|p| p.as_ref().to_vec()).collect();
+        self
+    }
+
+    /// random suites, group and versions, always usable together with ECDSA and RSA
+    /// server certificates
+    pub fn random() -> Self {
+        let protocol_versions = get_random_protocols();
+        let n = get_random_int(1, rustls::DEFAULT_CIPHER_SUITES.len() + 1);
+        let mut cipher_suites = get_random_ciphersuites(n);
+        // TLS 1.2 suites are tied to the certificate's key type
+        for sig_alg in [SignatureAlgorithm::ECDSA, SignatureAlgorithm::RSA] {
+            let usable = |suite: &SupportedCipherSuite| {
+                protocol_versions.contains(&suite.version())
+                    && suite.usable_for_signature_algorithm(sig_alg)
+            };
+            if !cipher_suites.iter().any(usable) {
+                let suite = rustls::DEFAULT_CIPHER_SUITES
+                    .iter()
+                    .find(|suite| usable(suite))
+                    .expect("every rustls version has a default suite for each key type");
+                cipher_suites.push(*suite);
+            }
+        }
+        Self::new(cipher_suites, get_random_kx_group(), protocol_versions)
+    }
+
+    /// like `random` restricted to TLS 1.3, as QUIC requires
+    pub fn random_tls13() -> Self {
+        let mut available: Vec<_> = rustls::DEFAULT_CIPHER_SUITES
+            .iter()
+            .copied()
+            .filter(|suite| suite.version() == &rustls::version::TLS13)
+            .collect();
+        let n = get_random_int(1, available.len() + 1);
+        let cipher_suites = (0..n)
+            .map(|_| available.remove(get_random_int(0, available.len())))
+            .collect();
+        Self::new(
+            cipher_suites,
+            get_random_kx_group(),
+            vec![&rustls::version::TLS13],
+        )
+    }
+
+    /// config builder waiting for a verifier, for connectors with custom verification
+    pub fn builder(&self) -> Result<ConfigBuilder<ClientConfig, WantsVerifier>> {
+        Ok(ClientConfig::builder()
+            .with_cipher_suites(&self.cipher_suites)
+            .with_kx_groups(&self.kx_groups)
+            .with_protocol_versions(&self.protocol_versions)?)
+    }
+
+    /// client config trusting `trust`, like `TrustStore::client_config`
+    pub fn client_config(&self, trust: &TrustStore) -> Result<ClientConfig> {
+        let mut config = self
+            .builder()?
+            .with_root_certificates(trust.root_store()?)
+            .with_no_client_auth();
+        config.alpn_protocols = self.alpn_protocols.clone();
+        Ok(config)
+    }
+
+    pub fn client_config_with_verifier(
+        &self,
+        verifier: Arc<dyn ServerCertVerifier>,
+    ) -> Result<ClientConfig> {
+        let mut config = self
+            .builder()?
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth();
+        config.alpn_protocols = self.alpn_protocols.clone();
+        Ok(config)
+    }
+
+    pub fn tls_connector(&self, trust: &TrustStore) -> Result<tokio_rustls::TlsConnector> {
+        Ok(tokio_rustls::TlsConnector::from(Arc::new(
+            self.client_config(trust)?,
+        )))
+    }
+}
+
+const ALPN_H2_HTTP11: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// a browser version family whose TLS handshake can be imitated
+///
+/// each preset offers the suites, groups and versions of that family in its order, as
+/// far as rustls supports them, and only pairs with user agents of the same family.
+/// rustls only has X25519, P-256 and P-384, so group lists are cut down to those.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BrowserPreset {
+    /// Chromium based browsers 79 to 97, before P-384 was offered
+    Chrome80,
+    /// Chromium based browsers 98 to 102, including Edge and Opera
+    Chrome100,
+    /// Firefox extended support releases 78 to 91
+    FirefoxEsr,
+    /// Firefox 99 to 101
+    Firefox100,
+    /// Safari 14 and 15 on macOS
+    Safari15,
+}
+
+impl BrowserPreset {
+    pub const ALL: [BrowserPreset; 5] = [
+        BrowserPreset::Chrome80,
+        BrowserPreset::Chrome100,
+        BrowserPreset::FirefoxEsr,
+        BrowserPreset::Firefox100,
+        BrowserPreset::Safari15,
+    ];
+
+    pub fn random() -> Self {
+        Self::ALL[get_random_int(0, Self::ALL.len())]
+    }
+
+    pub fn tls_profile(self) -> TlsProfile {
+        use rustls::kx_group::{SECP256R1, SECP384R1, X25519};
+        use rustls::version::{TLS12, TLS13};
+
+        let (cipher_suites, kx_groups, protocol_versions) = match self {
+            BrowserPreset::Chrome80 => (
+                chromium_suites(),
+                vec![&X25519, &SECP256R1],
+                vec![&TLS13, &TLS12],
+            ),
+            BrowserPreset::Chrome100 => (
+                chromium_suites(),
+                vec![&X25519, &SECP256R1, &SECP384R1],
+                vec![&TLS13, &TLS12],
+            ),
+            // ESR keeps the TLS 1.2 suites ahead of ChaCha20 like Firefox did before 80
+            BrowserPreset::FirefoxEsr => (
+                vec![
+                    TLS13_AES_128_GCM_SHA256,
+                    TLS13_CHACHA20_POLY1305_SHA256,
+                    TLS13_AES_256_GCM_SHA384,
+                    TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                    TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                ],
+                vec![&X25519, &SECP256R1, &SECP384R1],
+                vec![&TLS13, &TLS12],
+            ),
+            BrowserPreset::Firefox100 => (
+                vec![
+                    TLS13_AES_128_GCM_SHA256,
+                    TLS13_CHACHA20_POLY1305_SHA256,
+                    TLS13_AES_256_GCM_SHA384,
+                    TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                    TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                ],
+                vec![&X25519, &SECP256R1, &SECP384R1],
+                vec![&TLS13, &TLS12],
+            ),
+            BrowserPreset::Safari15 => (
+                vec![
+                    TLS13_AES_128_GCM_SHA256,
+                    TLS13_AES_256_GCM_SHA384,
+                    TLS13_CHACHA20_POLY1305_SHA256,
+                    TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                    TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                ],
+                vec![&X25519, &SECP384R1, &SECP256R1],
+                vec![&TLS13, &TLS12],
+            ),
+        };
+        TlsProfile::new(cipher_suites, kx_groups, protocol_versions)
+            .with_alpn_protocols(ALPN_H2_HTTP11)
+    }
+
+    /// whether `user_agent` belongs to a browser of this family
+    pub fn matches_user_agent(self, user_agent: &str) -> bool {
+        let chrome = product_version(user_agent, "Chrome/");
+        let firefox = product_version(user_agent, "Firefox/");
+        match self {
+            BrowserPreset::Chrome80 => chrome.is_some_and(|v| (79..=97).contains(&v)),
+            BrowserPreset::Chrome100 => chrome.is_some_and(|v| (98..=102).contains(&v)),
+            BrowserPreset::FirefoxEsr => firefox.is_some_and(|v| (78..=91).contains(&v)),
+            BrowserPreset::Firefox100 => firefox.is_some_and(|v| (99..=101).contains(&v)),
+            BrowserPreset::Safari15 => {
+                chrome.is_none()
+                    && user_agent.contains("Safari/")
+                    && product_version(user_agent, "Version/")
+                        .is_some_and(|v| (14..=15).contains(&v))
+            }
+        }
+    }
+
+    /// the preset for `user_agent`, if it is a browser we can imitate
+    pub fn for_user_agent(user_agent: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.matches_user_agent(user_agent))
+    }
+
+    /// agents of this family in the installed list, or in the built-in one if it has none
+    pub fn user_agents(self) -> Vec<Arc<str>> {
+        let agents: Vec<_> = user_agent::database()
+            .entries()
+            .iter()
+            .filter(|e| self.matches_user_agent(&e.user_agent))
+            .map(|e| e.user_agent.clone())
+            .collect();
+        if !agents.is_empty() {
+            return agents;
+        }
+        USER_AGENTS
+            .iter()
+            .filter(|ua| self.matches_user_agent(ua))
+            .map(|ua| Arc::from(*ua))
+            .collect()
+    }
+
+    /// a user agent of this family, chosen by weight
+    pub fn user_agent(self) -> Arc<str> {
+        user_agent::database()
+            .choose_matching(|e| self.matches_user_agent(&e.user_agent))
+            .unwrap_or_else(|| {
+                let mut agents = self.user_agents();
+                agents.swap_remove(get_random_int(0, agents.len()))
+            })
+    }
+}
+
+fn chromium_suites() -> Vec<SupportedCipherSuite> {
+    vec![
+        TLS13_AES_128_GCM_SHA256,
+        TLS13_AES_256_GCM_SHA384,
+        TLS13_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    ]
+}
+
+/// a random browser preset and a user agent of the same family
+///
+/// like every `get_random_*` helper the choice can be reproduced with `with_seed`.
+pub fn get_random_browser() -> (TlsProfile, Arc<str>) {
+    let preset = BrowserPreset::random();
+    (preset.tls_profile(), preset.user_agent())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_random_profiles_build_configs() -> anyhow::Result<()> {
+        let trust = TrustStore::new().with_webpki_roots();
+        for _ in 0..50 {
+            let profile = TlsProfile::random();
+            assert!(!profile.cipher_suites.is_empty());
+            assert_eq!(profile.kx_groups.len(), 1);
+            for sig_alg in [SignatureAlgorithm::ECDSA, SignatureAlgorithm::RSA] {
+                assert!(profile.cipher_suites.iter().any(|suite| {
+                    profile.protocol_versions.contains(&suite.version())
+                        && suite.usable_for_signature_algorithm(sig_alg)
+                }));
+            }
+            profile.client_config(&trust)?;
+        }
+        TlsProfile::default().client_config(&trust)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_seeded_choices_repeat() {
+        let pick = || {
+            (
+                format!("{:?}", TlsProfile::random()),
+                get_random_usr_agent(),
+                random_seed(),
+            )
+        };
+        let first = with_seed(42, pick);
+        assert_eq!(with_seed(42, pick), first);
+        assert_ne!(with_seed(43, pick), first);
+        // nested sources are restored on exit
+        let outer = with_seed(7, || {
+            with_seed(8, random_seed);
+            random_seed()
+        });
+        assert_eq!(outer, with_seed(7, random_seed));
+    }
+
+    #[test]
+    fn test_browser_presets_match_user_agents() -> anyhow::Result<()> {
+        let trust = TrustStore::new().with_webpki_roots();
+        for preset in BrowserPreset::ALL {
+            let config = preset.tls_profile().client_config(&trust)?;
+            assert_eq!(
+                config.alpn_protocols,
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            );
+            assert!(!preset.user_agents().is_empty());
+            for ua in preset.user_agents() {
+                assert_eq!(BrowserPreset::for_user_agent(&ua), Some(preset));
+            }
+        }
+        assert_eq!(
+            BrowserPreset::for_user_agent(
+                "Mozilla/5.0 (X11; U; Linux i686; en-US; rv:1.8.1.11) Gecko/20071204 Firefox/2.0.0.11"
+            ),
+            None
+        );
+        assert_eq!(
+            BrowserPreset::for_user_agent(
+                "Mozilla/5.0 (X11; Linux x86_64; rv:91.0) Gecko/20100101 Firefox/91.0"
+            ),
+            Some(BrowserPreset::FirefoxEsr)
+        );
+
+        // no two families send the same suites and groups
+        let fingerprint = |preset: BrowserPreset| {
+            let profile = preset.tls_profile();
+            let suites: Vec<_> = profile
+                .cipher_suites
+                .iter()
+                .map(|s| s.suite().get_u16())
+                .collect();
+            let groups: Vec<_> = profile.kx_groups.iter().map(|g| g.name.get_u16()).collect();
+            (suites, groups)
+        };
+        for (i, a) in BrowserPreset::ALL.iter().enumerate() {
+            for b in &BrowserPreset::ALL[i + 1..] {
+                assert_ne!(fingerprint(*a), fingerprint(*b), "{:?} and {:?}", a, b);
+            }
+        }
+        Ok(())
+    }
+}